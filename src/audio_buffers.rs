@@ -0,0 +1,477 @@
+use clack_host::prelude::*;
+use std::iter;
+
+/// A sample format the caller can process audio in.
+pub trait Sample: Copy + Default + 'static {
+    /// whether the plugin's ports are negotiated for 64-bit processing
+    const IS_F64: bool;
+
+    fn to_f32(self) -> f32;
+
+    fn to_f64(self) -> f64;
+
+    fn from_f32(sample: f32) -> Self;
+
+    fn from_f64(sample: f64) -> Self;
+
+    /// Hands the caller's output buffers to the plugin as they are.
+    fn output_only<'a>(
+        channels: impl IntoIterator<Item = &'a mut [Self]>,
+    ) -> AudioPortBufferType<'a>;
+
+    /// Picks the buffers of this sample format.
+    fn select<'a>(f32: &'a mut [Vec<f32>], f64: &'a mut [Vec<f64>]) -> &'a mut [Vec<Self>];
+}
+
+impl Sample for f32 {
+    const IS_F64: bool = false;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn to_f64(self) -> f64 {
+        self.into()
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample
+    }
+
+    fn from_f64(sample: f64) -> Self {
+        sample as Self
+    }
+
+    fn output_only<'a>(
+        channels: impl IntoIterator<Item = &'a mut [Self]>,
+    ) -> AudioPortBufferType<'a> {
+        AudioPortBufferType::f32_output_only(channels)
+    }
+
+    fn select<'a>(f32: &'a mut [Vec<f32>], _: &'a mut [Vec<f64>]) -> &'a mut [Vec<Self>] {
+        f32
+    }
+}
+
+impl Sample for f64 {
+    const IS_F64: bool = true;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample.into()
+    }
+
+    fn from_f64(sample: f64) -> Self {
+        sample
+    }
+
+    fn output_only<'a>(
+        channels: impl IntoIterator<Item = &'a mut [Self]>,
+    ) -> AudioPortBufferType<'a> {
+        AudioPortBufferType::f64_output_only(channels)
+    }
+
+    fn select<'a>(_: &'a mut [Vec<f32>], f64: &'a mut [Vec<f64>]) -> &'a mut [Vec<Self>] {
+        f64
+    }
+}
+
+/// Buffers per port and channel, for audio that can't be handed to the plugin as it is.
+pub struct PortBuffers<T>(Vec<Vec<Vec<T>>>);
+
+impl<T: Sample> PortBuffers<T> {
+    /// Makes sure there's room for ports of up to these channel counts and up to `frames` frames, and returns whether
+    /// anything had to be allocated.
+    pub fn reserve(
+        &mut self,
+        channels: impl ExactSizeIterator<Item = usize>,
+        frames: usize,
+    ) -> bool {
+        let mut grown = self.0.len() < channels.len();
+        if grown {
+            self.0.resize_with(channels.len(), Vec::new);
+        }
+
+        let mut channels = channels.fuse();
+        for buffers in &mut self.0 {
+            let channels = channels.next().unwrap_or(0).max(buffers.len());
+
+            if buffers.len() < channels
+                || buffers.first().is_some_and(|buffer| buffer.len() < frames)
+            {
+                *buffers = vec![vec![T::default(); frames]; channels];
+                grown = true;
+            }
+        }
+
+        grown
+    }
+}
+
+impl<T> Default for PortBuffers<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+/// Buffers in both sample formats, so that every port can be handed the one it's negotiated for.
+#[derive(Default)]
+pub struct SampleBuffers {
+    f32: PortBuffers<f32>,
+    f64: PortBuffers<f64>,
+}
+
+impl SampleBuffers {
+    /// See [`PortBuffers::reserve`].
+    pub fn reserve(
+        &mut self,
+        channels: impl ExactSizeIterator<Item = usize> + Clone,
+        frames: usize,
+    ) -> bool {
+        let f32_grown = self.f32.reserve(channels.clone(), frames);
+        let f64_grown = self.f64.reserve(channels, frames);
+
+        f32_grown || f64_grown
+    }
+
+    /// Audio ports with room for all of the buffers.
+    pub fn audio_ports(&self) -> AudioPorts {
+        AudioPorts::with_capacity(self.f32.0.iter().map(Vec::len).sum(), self.f32.0.len())
+    }
+
+    /// Copies the first `frames` frames of `audio` into the buffers of the sample format each port is negotiated
+    /// for. Channels the caller didn't pass are silent.
+    pub fn copy_from<T: Sample>(
+        &mut self,
+        audio: &[&[&[T]]],
+        formats: &PortFormats,
+        frames: usize,
+    ) {
+        for index in 0..formats.port_count(audio.len()) {
+            let port = audio.get(index).copied().unwrap_or_default();
+
+            for channel in 0..formats.channel_count(index, port.len()) {
+                let input = port.get(channel).map(|buffer| &buffer[..frames]);
+
+                if formats.is_f64::<T>(index) {
+                    copy_or_silence(input, &mut self.f64.0[index][channel][..frames]);
+                } else {
+                    copy_or_silence(input, &mut self.f32.0[index][channel][..frames]);
+                }
+            }
+        }
+    }
+
+    /// Hands the plugin the first `frames` frames of the buffers [`Self::copy_from`] copied into.
+    pub fn input_port_buffers<'a, T: Sample>(
+        &'a mut self,
+        audio: &'a [&[&[T]]],
+        formats: &'a PortFormats,
+        frames: usize,
+    ) -> impl Iterator<Item = AudioPortBuffer<'a>> {
+        self.f32
+            .0
+            .iter_mut()
+            .zip(&mut self.f64.0)
+            .enumerate()
+            .take(formats.port_count(audio.len()))
+            .map(move |(index, (f32_buffers, f64_buffers))| {
+                let channels =
+                    formats.channel_count(index, audio.get(index).map_or(0, |port| port.len()));
+
+                AudioPortBuffer {
+                    latency: 0,
+                    channels: if formats.is_f64::<T>(index) {
+                        AudioPortBufferType::f64_input_only(
+                            f64_buffers[..channels]
+                                .iter_mut()
+                                .map(|buffer| InputChannel::constant(&mut buffer[..frames])),
+                        )
+                    } else {
+                        AudioPortBufferType::f32_input_only(
+                            f32_buffers[..channels]
+                                .iter_mut()
+                                .map(|buffer| InputChannel::constant(&mut buffer[..frames])),
+                        )
+                    },
+                }
+            })
+    }
+
+    /// Hands the plugin the first `frames` frames of the caller's buffers where they're of the sample format the port
+    /// is negotiated for, and of these buffers otherwise, which [`Self::copy_to`] copies from.
+    pub fn output_port_buffers<'a, 'b: 'a, 'c: 'a, T: Sample>(
+        &'a mut self,
+        audio: &'a mut [&'b mut [&'c mut [T]]],
+        formats: &'a PortFormats,
+        frames: usize,
+    ) -> impl Iterator<Item = AudioPortBuffer<'a>> + use<'a, 'b, 'c, T> {
+        let ports = formats.port_count(audio.len());
+
+        audio
+            .iter_mut()
+            .map(Some)
+            .chain(iter::repeat_with(|| None))
+            .zip(&mut self.f32.0)
+            .zip(&mut self.f64.0)
+            .enumerate()
+            .take(ports)
+            .map(move |(index, ((port, f32_buffers), f64_buffers))| {
+                let port = port.map_or(&mut [][..], |port| &mut **port);
+                let channels = formats.channel_count(index, port.len());
+                let provided = port.len().min(channels);
+
+                AudioPortBuffer {
+                    latency: 0,
+                    channels: if formats.is_f64::<T>(index) == T::IS_F64 {
+                        T::output_only(
+                            port[..provided]
+                                .iter_mut()
+                                .map(|buffer| &mut buffer[..frames])
+                                .chain(
+                                    T::select(f32_buffers, f64_buffers)[provided..channels]
+                                        .iter_mut()
+                                        .map(|buffer| &mut buffer[..frames]),
+                                ),
+                        )
+                    } else if T::IS_F64 {
+                        AudioPortBufferType::f32_output_only(
+                            f32_buffers[..channels]
+                                .iter_mut()
+                                .map(|buffer| &mut buffer[..frames]),
+                        )
+                    } else {
+                        AudioPortBufferType::f64_output_only(
+                            f64_buffers[..channels]
+                                .iter_mut()
+                                .map(|buffer| &mut buffer[..frames]),
+                        )
+                    },
+                }
+            })
+    }
+
+    /// Copies the first `frames` frames of the ports that aren't negotiated for `T` into `audio`. Channels the plugin
+    /// doesn't have are silent.
+    pub fn copy_to<T: Sample>(
+        &self,
+        audio: &mut [&mut [&mut [T]]],
+        formats: &PortFormats,
+        frames: usize,
+    ) {
+        let ports = formats.port_count(audio.len());
+
+        for (index, port) in audio.iter_mut().enumerate() {
+            let channels = if index < ports {
+                formats.channel_count(index, port.len())
+            } else {
+                0
+            };
+
+            for (channel, buffer) in port.iter_mut().enumerate() {
+                let buffer = &mut buffer[..frames];
+
+                if channel >= channels {
+                    buffer.fill(T::default());
+                } else if formats.is_f64::<T>(index) == T::IS_F64 {
+                    // the plugin wrote into the caller's buffer directly
+                } else if T::IS_F64 {
+                    convert(&self.f32.0[index][channel][..frames], buffer);
+                } else {
+                    convert(&self.f64.0[index][channel][..frames], buffer);
+                }
+            }
+        }
+    }
+}
+
+/// What the plugin reported about its input or its output ports.
+#[derive(Default)]
+pub struct PortFormats {
+    /// how many channels each port has, or `None` if the plugin's ports aren't known, in which case it's handed the
+    /// ports and channels the caller passes
+    pub channels: Option<Vec<usize>>,
+    /// which ports are handed 64-bit buffers, when processing 32-bit and 64-bit audio respectively
+    pub f64: [Vec<bool>; 2],
+}
+
+impl PortFormats {
+    fn port_count(&self, provided: usize) -> usize {
+        self.channels.as_ref().map_or(provided, Vec::len)
+    }
+
+    fn channel_count(&self, index: usize, provided: usize) -> usize {
+        self.channels
+            .as_ref()
+            .map_or(provided, |channels| channels[index])
+    }
+
+    /// Ports the plugin didn't report are handed 32-bit buffers.
+    fn is_f64<T: Sample>(&self, index: usize) -> bool {
+        self.f64[usize::from(T::IS_F64)]
+            .get(index)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+/// Converts `from` into `to`, up to the shorter of the two.
+pub fn convert<F: Sample, T: Sample>(from: &[F], to: &mut [T]) {
+    for (to, &from) in to.iter_mut().zip(from) {
+        *to = if T::IS_F64 {
+            T::from_f64(from.to_f64())
+        } else {
+            T::from_f32(from.to_f32())
+        };
+    }
+}
+
+fn copy_or_silence<F: Sample, T: Sample>(from: Option<&[F]>, to: &mut [T]) {
+    match from {
+        Some(from) => convert(from, to),
+        None => to.fill(T::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// buffers for two stereo ports, filled with ones so that silenced channels stand out
+    fn buffers(frames: usize) -> SampleBuffers {
+        let mut buffers = SampleBuffers::default();
+        buffers.reserve([2, 2].into_iter(), frames);
+
+        for port in &mut buffers.f32.0 {
+            for channel in port {
+                channel.fill(1.0);
+            }
+        }
+        for port in &mut buffers.f64.0 {
+            for channel in port {
+                channel.fill(1.0);
+            }
+        }
+
+        buffers
+    }
+
+    #[test]
+    fn samples_convert_between_formats() {
+        // these samples are exact in both formats
+        let mut f64 = vec![0.0f64; 3];
+        convert(&[0.5f32, -1.0, 0.25], &mut f64);
+        assert_eq!(f64, [0.5, -1.0, 0.25]);
+
+        // only as much as fits is converted
+        let mut f32 = vec![0.0f32; 2];
+        convert(&f64, &mut f32);
+        assert_eq!(f32, [0.5, -1.0]);
+    }
+
+    #[test]
+    fn unreported_ports_are_f32() {
+        let formats = PortFormats {
+            channels: Some(vec![2, 2]),
+            f64: [vec![false], vec![true]],
+        };
+
+        assert!(!formats.is_f64::<f32>(0));
+        assert!(formats.is_f64::<f64>(0));
+        assert!(!formats.is_f64::<f64>(1));
+    }
+
+    #[test]
+    fn missing_input_channels_are_silent() {
+        let mut buffers = buffers(2);
+        let formats = PortFormats {
+            channels: Some(vec![2, 2]),
+            f64: [vec![false, false], vec![false, false]],
+        };
+
+        // the caller only passes a mono first port
+        buffers.copy_from::<f32>(&[&[&[0.5, 0.25]]], &formats, 2);
+
+        assert_eq!(buffers.f32.0[0][0], [0.5, 0.25]);
+        assert_eq!(buffers.f32.0[0][1], [0.0, 0.0]);
+        assert_eq!(buffers.f32.0[1], [[0.0, 0.0], [0.0, 0.0]]);
+    }
+
+    #[test]
+    fn inputs_are_copied_into_the_negotiated_format() {
+        let mut buffers = buffers(2);
+        let formats = PortFormats {
+            channels: Some(vec![1, 1]),
+            f64: [vec![false, true], vec![false, false]],
+        };
+
+        buffers.copy_from::<f32>(&[&[&[0.5, 0.25]], &[&[-0.5, -0.25]]], &formats, 2);
+
+        assert_eq!(buffers.f32.0[0][0], [0.5, 0.25]);
+        assert_eq!(buffers.f64.0[1][0], [-0.5, -0.25]);
+        // the other format's buffers aren't touched
+        assert_eq!(buffers.f64.0[0][0], [1.0, 1.0]);
+        assert_eq!(buffers.f32.0[1][0], [1.0, 1.0]);
+    }
+
+    #[test]
+    fn outputs_are_converted_and_missing_channels_silenced() {
+        let mut buffers = buffers(2);
+        buffers.f64.0[0][0].copy_from_slice(&[0.5, 0.25]);
+
+        // the plugin has a single mono output, negotiated for 64-bit buffers
+        let formats = PortFormats {
+            channels: Some(vec![1]),
+            f64: [vec![true], vec![true]],
+        };
+
+        let mut left = vec![2.0f32; 2];
+        let mut right = vec![2.0f32; 2];
+        let mut aux = vec![2.0f32; 2];
+        buffers.copy_to::<f32>(
+            &mut [
+                &mut [left.as_mut_slice(), right.as_mut_slice()],
+                &mut [aux.as_mut_slice()],
+            ],
+            &formats,
+            2,
+        );
+
+        assert_eq!(left, [0.5, 0.25]);
+        assert_eq!(right, [0.0, 0.0]);
+        assert_eq!(aux, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn unknown_ports_follow_the_caller() {
+        let mut buffers = buffers(2);
+        let formats = PortFormats {
+            channels: None,
+            f64: [Vec::new(), Vec::new()],
+        };
+
+        buffers.copy_from::<f64>(&[&[&[0.5, 0.25], &[-0.5, -0.25]]], &formats, 2);
+
+        assert_eq!(buffers.f32.0[0], [[0.5, 0.25], [-0.5, -0.25]]);
+        // ports the caller didn't pass aren't handed to the plugin at all
+        assert_eq!(buffers.f32.0[1], [[1.0, 1.0], [1.0, 1.0]]);
+
+        let mut left = vec![2.0f64; 2];
+        let mut right = vec![2.0f64; 2];
+        buffers.copy_to::<f64>(
+            &mut [&mut [left.as_mut_slice(), right.as_mut_slice()]],
+            &formats,
+            2,
+        );
+
+        assert_eq!(left, [0.5, 0.25]);
+        assert_eq!(right, [-0.5, -0.25]);
+    }
+}
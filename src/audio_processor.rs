@@ -1,67 +1,671 @@
-use crate::Host;
-use clack_host::{prelude::*, process::StartedPluginAudioProcessor};
-use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+use crate::{
+    audio_buffers::{PortFormats, Sample, SampleBuffers},
+    Host, HostError, PluginError,
+};
+#[cfg(feature = "audio-ports")]
+use crate::{AudioPort, AudioPortLayout};
+use clack_host::{
+    prelude::*,
+    process::{StartedPluginAudioProcessor, StoppedPluginAudioProcessor},
+};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    iter, mem, ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+/// how many commands the main thread can queue up for the audio thread
+const COMMAND_CAPACITY: usize = 64;
+
+/// how many channels the buffers of the main ports are allocated for up front, if the plugin's ports aren't known
+const DEFAULT_CHANNELS: usize = 2;
+
+/// Sent from the main thread to the audio thread, which applies it before processing the next block.
+pub enum AudioCommand {
+    RequestProcess,
+    /// hand the audio processor over to the main thread, e.g. so that it can reactivate the plugin
+    HandOver,
+}
+
+enum Processor {
+    Started(StartedPluginAudioProcessor<Host>),
+    /// the plugin is activated, but asleep or not started yet
+    Stopped(StoppedPluginAudioProcessor<Host>),
+    /// the plugin failed to reactivate
+    Inactive,
+}
 
 pub struct AudioProcessor {
-    started_audio_processor: Option<StartedPluginAudioProcessor<Host>>,
-    steady_time: AtomicU64,
+    processor: Processor,
+    config: PluginAudioConfiguration,
+    /// the plugin returned `ContinueIfNotQuiet` from its last process call
+    sleep_if_quiet: bool,
+    /// the plugin's output was silent in its last process call, so that e.g. reverb tails aren't cut off
+    output_quiet: bool,
+    /// the plugin called `request_process` since its last process call
+    process_requested: bool,
+    /// the input is copied here, in the sample format of its port, since the plugin is handed mutable input buffers
+    input_buffers: SampleBuffers,
+    /// the output of ports that aren't handed the caller's sample format is converted from here
+    output_buffers: SampleBuffers,
+    input_ports: AudioPorts,
+    output_ports: AudioPorts,
+    input_formats: PortFormats,
+    output_formats: PortFormats,
 }
 
 impl AudioProcessor {
-    pub fn new(audio_processor: StartedPluginAudioProcessor<Host>) -> Self {
-        Self {
-            started_audio_processor: Some(audio_processor),
-            steady_time: AtomicU64::new(0),
+    /// The plugin starts processing on the audio thread, with the first block it's asked to process.
+    pub fn new(
+        audio_processor: StoppedPluginAudioProcessor<Host>,
+        config: PluginAudioConfiguration,
+    ) -> Self {
+        let mut processor = Self {
+            processor: Processor::Stopped(audio_processor),
+            config,
+            sleep_if_quiet: false,
+            output_quiet: true,
+            process_requested: true,
+            input_buffers: SampleBuffers::default(),
+            output_buffers: SampleBuffers::default(),
+            input_ports: AudioPorts::with_capacity(0, 0),
+            output_ports: AudioPorts::with_capacity(0, 0),
+            input_formats: PortFormats::default(),
+            output_formats: PortFormats::default(),
+        };
+
+        processor.reserve(
+            iter::once(DEFAULT_CHANNELS),
+            iter::once(DEFAULT_CHANNELS),
+            0,
+        );
+
+        processor
+    }
+
+    /// Hands the audio processor to the audio thread, which hands it over to the main thread when asked to.
+    pub fn split(self) -> (MainThreadProcessor, AudioThreadProcessor) {
+        let (commands, command_receiver) = RingBuffer::new(COMMAND_CAPACITY);
+        let slots = Arc::new(Slots::default());
+
+        (
+            MainThreadProcessor {
+                commands,
+                slots: slots.clone(),
+                processor: None,
+                hand_over_requested: false,
+            },
+            AudioThreadProcessor {
+                processor: Some(Box::new(self)),
+                commands: command_receiver,
+                slots,
+                released: false,
+                steady_time: 0,
+            },
+        )
+    }
+
+    /// Makes sure processing blocks with ports of up to these channel counts and up to `frames` frames doesn't
+    /// allocate.
+    ///
+    /// Buffers for both sample formats are allocated, so that switching between them doesn't allocate either.
+    fn reserve(
+        &mut self,
+        input_channels: impl ExactSizeIterator<Item = usize> + Clone,
+        output_channels: impl ExactSizeIterator<Item = usize> + Clone,
+        frames: usize,
+    ) {
+        let frames = frames.max(self.config.max_frames_count as usize);
+
+        if self.input_buffers.reserve(input_channels, frames) {
+            self.input_ports = self.input_buffers.audio_ports();
+        }
+
+        if self.output_buffers.reserve(output_channels, frames) {
+            self.output_ports = self.output_buffers.audio_ports();
         }
     }
 
-    pub fn steady_time(&self) -> u64 {
-        self.steady_time.load(SeqCst)
+    /// Hands the plugin buffers for exactly the ports and channels it has from now on, negotiates the sample format
+    /// of each port, and makes sure processing blocks with them doesn't allocate.
+    #[cfg(feature = "audio-ports")]
+    pub fn set_ports(&mut self, ports: &AudioPortLayout) {
+        let (inputs_f32, outputs_f32) = ports.f64_ports(false);
+        let (inputs_f64, outputs_f64) = ports.f64_ports(true);
+        let channels = |ports: &[AudioPort]| {
+            ports
+                .iter()
+                .map(|port| port.channel_count as usize)
+                .collect::<Vec<_>>()
+        };
+
+        self.input_formats = PortFormats {
+            channels: Some(channels(&ports.inputs)),
+            f64: [inputs_f32, inputs_f64],
+        };
+        self.output_formats = PortFormats {
+            channels: Some(channels(&ports.outputs)),
+            f64: [outputs_f32, outputs_f64],
+        };
+
+        self.reserve(
+            ports.inputs.iter().map(|port| port.channel_count as usize),
+            ports.outputs.iter().map(|port| port.channel_count as usize),
+            0,
+        );
+    }
+
+    pub fn request_process(&mut self) {
+        self.process_requested = true;
     }
 
-    pub fn process(
+    /// Processes one block of audio, writing the plugin's output into `output_audio` and `output_events`.
+    ///
+    /// The audio is passed per port, and then per channel. Once the plugin's ports are known, it's handed buffers for
+    /// exactly those: ports and channels the caller doesn't pass are silent, or their output is dropped, and the ones
+    /// the plugin doesn't have are ignored, or silent. Ports are handed the caller's sample format if they support it,
+    /// and the audio is converted otherwise. The block is as long as the shortest of the buffers. Nothing is
+    /// allocated, unless the block is longer than the plugin was activated for, has more ports or channels than any
+    /// block before it, or the plugin outputs more events than fit into `output_events`.
+    ///
+    /// `steady_time` is advanced by the length of the block.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin fails to process the audio, and with [`PluginError::NotProcessing`] if it refuses to start
+    /// processing, in which case the output is silent
+    pub fn process<T: Sample>(
         &mut self,
-        #[expect(clippy::ptr_arg)] input_audio_buffers: &mut Vec<Vec<f32>>,
-        input_events_buffer: &EventBuffer,
-        input_ports: &mut AudioPorts,
-        output_ports: &mut AudioPorts,
-    ) -> (Vec<Vec<f32>>, EventBuffer) {
-        let mut output_audio_buffers = input_audio_buffers.clone();
-
-        let input_audio = input_ports.with_input_buffers([AudioPortBuffer {
-            latency: 0,
-            channels: AudioPortBufferType::f32_input_only(
-                input_audio_buffers.iter_mut().map(InputChannel::constant),
-            ),
-        }]);
-
-        let mut output_audio = output_ports.with_output_buffers([AudioPortBuffer {
-            latency: 0,
-            channels: AudioPortBufferType::f32_output_only(
-                output_audio_buffers.iter_mut().map(Vec::as_mut_slice),
-            ),
-        }]);
-
-        let input_events = InputEvents::from_buffer(input_events_buffer);
-        let mut output_events_buffer = EventBuffer::new();
-        let mut output_events = OutputEvents::from_buffer(&mut output_events_buffer);
-
-        self.started_audio_processor
-            .as_mut()
-            .unwrap()
+        steady_time: &mut u64,
+        input_audio: &[&[&[T]]],
+        output_audio: &mut [&mut [&mut [T]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        let frames = block_frames(input_audio, output_audio);
+
+        output_events.clear();
+
+        let is_quiet = input_events.is_empty()
+            && is_silent(
+                input_audio.iter().flat_map(|port| port.iter().copied()),
+                frames,
+            );
+
+        let woken = if mem::take(&mut self.process_requested) || !is_quiet {
+            self.wake()
+        } else {
+            if self.sleep_if_quiet && self.output_quiet {
+                self.sleep();
+            }
+
+            Ok(())
+        };
+
+        self.reserve(
+            input_audio.iter().map(|port| port.len()),
+            output_audio.iter().map(|port| port.len()),
+            frames,
+        );
+
+        // a sleeping plugin, or one that failed to reactivate or to start processing, outputs silence
+        let Processor::Started(started_audio_processor) = &mut self.processor else {
+            silence(output_audio);
+            *steady_time += frames as u64;
+
+            return woken;
+        };
+
+        self.input_buffers
+            .copy_from(input_audio, &self.input_formats, frames);
+
+        let input_buffers =
+            self.input_ports
+                .with_input_buffers(self.input_buffers.input_port_buffers(
+                    input_audio,
+                    &self.input_formats,
+                    frames,
+                ));
+
+        let mut output_buffers =
+            self.output_ports
+                .with_output_buffers(self.output_buffers.output_port_buffers(
+                    output_audio,
+                    &self.output_formats,
+                    frames,
+                ));
+
+        let input_events = InputEvents::from_buffer(input_events);
+        let mut output_events = OutputEvents::from_buffer(output_events);
+
+        let status = started_audio_processor
             .process(
-                &input_audio,
-                &mut output_audio,
+                &input_buffers,
+                &mut output_buffers,
                 &input_events,
                 &mut output_events,
-                Some(self.steady_time.load(SeqCst)),
+                Some(*steady_time),
+                None,
+            )
+            .map_err(PluginError::Process)?;
+
+        self.output_buffers
+            .copy_to(output_audio, &self.output_formats, frames);
+
+        self.output_quiet = is_silent(
+            output_audio
+                .iter()
+                .flat_map(|port| port.iter().map(|buffer| &**buffer)),
+            frames,
+        );
+
+        *steady_time += frames as u64;
+
+        self.sleep_if_quiet = status == ProcessStatus::ContinueIfNotQuiet;
+        if status == ProcessStatus::Sleep {
+            self.sleep();
+        }
+
+        Ok(())
+    }
+
+    /// Hands buffers the caller set up itself straight to the plugin's audio processor, and returns the status the
+    /// plugin returned.
+    ///
+    /// The plugin is started if it isn't processing, but never put to sleep: the caller decides what to do with the
+    /// status.
+    ///
+    /// # Errors
+    ///
+    /// errors with [`PluginError::NotProcessing`] if the plugin couldn't be started, in which case the output buffers
+    /// weren't written to
+    pub fn process_raw(
+        &mut self,
+        steady_time: &mut u64,
+        input_audio: &InputAudioBuffers<'_>,
+        output_audio: &mut OutputAudioBuffers<'_>,
+        input_events: &InputEvents<'_>,
+        output_events: &mut OutputEvents<'_>,
+    ) -> Result<ProcessStatus, PluginError> {
+        self.process_requested = false;
+        self.sleep_if_quiet = false;
+        self.wake()?;
+
+        let Processor::Started(started_audio_processor) = &mut self.processor else {
+            return Err(PluginError::NotProcessing);
+        };
+
+        let status = started_audio_processor
+            .process(
+                input_audio,
+                output_audio,
+                input_events,
+                output_events,
+                Some(*steady_time),
                 None,
             )
-            .unwrap();
+            .map_err(PluginError::Process)?;
+
+        let frames = output_audio
+            .frames_count()
+            .or_else(|| input_audio.frames_count())
+            .unwrap_or(0);
+        *steady_time += u64::from(frames);
+
+        Ok(status)
+    }
+
+    /// Starts processing if the plugin isn't processing, which CLAP requires to happen on the audio thread, so the
+    /// plugin is only ever started here.
+    ///
+    /// Errors with [`PluginError::NotProcessing`] if the plugin refuses to start, in which case it's tried again with
+    /// the next block.
+    fn wake(&mut self) -> Result<(), PluginError> {
+        self.processor = match mem::replace(&mut self.processor, Processor::Inactive) {
+            Processor::Stopped(stopped_audio_processor) => {
+                match stopped_audio_processor.start_processing() {
+                    Ok(started_audio_processor) => Processor::Started(started_audio_processor),
+                    Err(err) => {
+                        self.processor = Processor::Stopped(err.into_stopped_processor());
+                        self.process_requested = true;
+
+                        return Err(PluginError::NotProcessing);
+                    }
+                }
+            }
+            processor => processor,
+        };
+
+        Ok(())
+    }
 
+    fn sleep(&mut self) {
+        self.processor = match mem::replace(&mut self.processor, Processor::Inactive) {
+            Processor::Started(started_audio_processor) => {
+                Processor::Stopped(started_audio_processor.stop_processing())
+            }
+            processor => processor,
+        };
+        self.sleep_if_quiet = false;
+    }
+
+    /// Stops processing on the audio thread, as CLAP requires, before the audio processor is handed over to the main
+    /// thread. A plugin that was processing starts again with the first block after it's handed back.
+    fn stop_for_hand_over(&mut self) {
+        if matches!(self.processor, Processor::Started(_)) {
+            self.sleep();
+            self.process_requested = true;
+        }
+    }
+
+    /// Deactivates the plugin and activates it again with the current configuration.
+    pub fn restart(&mut self, instance: &mut PluginInstance<Host>) -> Result<(), HostError> {
+        self.reconfigure(instance, self.config)
+    }
+
+    /// Deactivates the plugin, and activates it again with `config`.
+    pub fn reconfigure(
+        &mut self,
+        instance: &mut PluginInstance<Host>,
+        config: PluginAudioConfiguration,
+    ) -> Result<(), HostError> {
+        self.config = config;
+        self.deactivate(instance);
+        self.reserve(iter::empty(), iter::empty(), 0);
+
+        let stopped_audio_processor = instance
+            .activate(|_, _| {}, config)
+            .map_err(HostError::Activation)?;
+
+        #[cfg(feature = "audio-ports")]
+        self.set_ports(&AudioPortLayout::query(instance));
+
+        self.processor = Processor::Stopped(stopped_audio_processor);
+        self.process_requested = true;
+
+        Ok(())
+    }
+
+    /// Deactivates the plugin, if it's active.
+    ///
+    /// Only called on the main thread, once the audio thread stopped processing and handed the audio processor over.
+    pub fn deactivate(&mut self, instance: &mut PluginInstance<Host>) {
+        self.sleep_if_quiet = false;
+
+        match mem::replace(&mut self.processor, Processor::Inactive) {
+            Processor::Started(_) => {
+                unreachable!(
+                    "the audio thread stops processing before handing the audio processor over"
+                )
+            }
+            Processor::Stopped(stopped_audio_processor) => {
+                instance.deactivate(stopped_audio_processor);
+            }
+            Processor::Inactive => {}
+        }
+    }
+}
+
+/// Holds the audio processor while it's moved from one thread to the other, until either of them takes it.
+#[derive(Default)]
+struct Slot(AtomicPtr<AudioProcessor>);
+
+impl Slot {
+    fn put(&self, processor: Box<AudioProcessor>) {
+        let previous = self.0.swap(Box::into_raw(processor), Ordering::AcqRel);
+        debug_assert!(previous.is_null(), "there's only one audio processor");
+    }
+
+    fn take(&self) -> Option<Box<AudioProcessor>> {
+        let processor = self.0.swap(ptr::null_mut(), Ordering::AcqRel);
+
+        // SAFETY: the slot only ever holds pointers `put` got from `Box::into_raw`, and swapping one out makes the
+        // caller its only owner
+        (!processor.is_null()).then(|| unsafe { Box::from_raw(processor) })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    /// handed over by the audio thread
+    to_main: Slot,
+    /// handed back by the main thread
+    to_audio: Slot,
+}
+
+/// The main thread's side of an [`AudioProcessor`], which is moved between the main thread and the audio thread.
+///
+/// The audio thread owns the audio processor, and hands it over when the main thread asks for it, e.g. to reactivate
+/// the plugin, or when it's released, see [`AudioThreadProcessor::release`]. Neither thread ever waits for the other.
+pub struct MainThreadProcessor {
+    commands: Producer<AudioCommand>,
+    slots: Arc<Slots>,
+    /// set while the audio thread has handed the audio processor over
+    processor: Option<Box<AudioProcessor>>,
+    hand_over_requested: bool,
+}
+
+impl MainThreadProcessor {
+    /// Asks the audio thread to hand the audio processor over with its next block, see [`Self::get`].
+    pub fn request_hand_over(&mut self) {
+        if self.processor.is_none() && !self.hand_over_requested {
+            // if the queue is full, the request is sent again with the next call
+            self.hand_over_requested = self.commands.push(AudioCommand::HandOver).is_ok();
+        }
+    }
+
+    /// The audio processor, if the audio thread has handed it over or released it.
+    pub fn get(&mut self) -> Option<&mut AudioProcessor> {
+        if self.processor.is_none() {
+            self.processor = self.slots.to_main.take().or_else(|| {
+                // the audio thread may have been dropped right after the audio processor was handed back
+                self.audio_thread_gone()
+                    .then(|| self.slots.to_audio.take())
+                    .flatten()
+            });
+        }
+
+        if self.processor.is_some() {
+            self.hand_over_requested = false;
+        }
+
+        self.processor.as_deref_mut()
+    }
+
+    /// Hands the audio processor back to the audio thread, which processes with it again from its next block on.
+    ///
+    /// The audio processor is kept if the audio thread is gone.
+    pub fn hand_back(&mut self) {
+        if self.audio_thread_gone() {
+            return;
+        }
+
+        if let Some(processor) = self.processor.take() {
+            self.slots.to_audio.put(processor);
+        }
+    }
+
+    fn audio_thread_gone(&self) -> bool {
+        Arc::strong_count(&self.slots) == 1
+    }
+
+    pub fn request_process(&mut self) {
+        match &mut self.processor {
+            Some(processor) => processor.request_process(),
+            None => {
+                // a full queue already holds a request to process
+                self.commands.push(AudioCommand::RequestProcess).ok();
+            }
+        }
+    }
+}
+
+/// The audio thread's side of an [`AudioProcessor`], which is moved between the main thread and the audio thread.
+///
+/// Processing never blocks: while the audio processor is handed over to the main thread, blocks are silent. Dropping
+/// this hands the audio processor over, so that the main thread can deactivate the plugin.
+pub struct AudioThreadProcessor {
+    /// `None` while it's handed over to the main thread
+    processor: Option<Box<AudioProcessor>>,
+    commands: Consumer<AudioCommand>,
+    slots: Arc<Slots>,
+    /// the audio processor was handed over by [`Self::release`], so it can be taken back unless the main thread took
+    /// it in the meantime
+    released: bool,
+    /// advanced even while the audio processor is handed over
+    steady_time: u64,
+}
+
+impl AudioThreadProcessor {
+    pub const fn steady_time(&self) -> u64 {
         self.steady_time
-            .fetch_add(u64::from(output_audio.frames_count().unwrap()), SeqCst);
+    }
+
+    /// Stops processing and hands the audio processor over until the next block, so that the main thread can take it
+    /// without waiting for a block, e.g. while the audio device is stopped.
+    pub fn release(&mut self) {
+        self.handle_commands();
+
+        if self.processor.is_some() {
+            self.hand_over();
+            self.released = true;
+        }
+    }
+
+    /// Takes the audio processor back if the main thread handed it back, or didn't take it after it was released, and
+    /// applies the main thread's commands.
+    fn handle_commands(&mut self) {
+        if self.processor.is_none() {
+            self.processor = self
+                .slots
+                .to_audio
+                .take()
+                .or_else(|| self.released.then(|| self.slots.to_main.take()).flatten());
+
+            if self.processor.is_some() {
+                self.released = false;
+            }
+        }
+
+        while let Ok(command) = self.commands.pop() {
+            match command {
+                AudioCommand::RequestProcess => {
+                    if let Some(processor) = &mut self.processor {
+                        processor.request_process();
+                    }
+                }
+                AudioCommand::HandOver => self.hand_over(),
+            }
+        }
+    }
+
+    fn hand_over(&mut self) {
+        // the main thread asked for it, so a released audio processor isn't taken back either
+        self.released = false;
+
+        if let Some(mut processor) = self.processor.take() {
+            processor.stop_for_hand_over();
+            self.slots.to_main.put(processor);
+        }
+    }
+
+    /// See [`AudioProcessor::process`].
+    pub fn process<T: Sample>(
+        &mut self,
+        input_audio: &[&[&[T]]],
+        output_audio: &mut [&mut [&mut [T]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        self.handle_commands();
+
+        let Some(processor) = &mut self.processor else {
+            silence(output_audio);
+            output_events.clear();
+            self.steady_time += block_frames(input_audio, output_audio) as u64;
+
+            return Ok(());
+        };
+
+        processor.process(
+            &mut self.steady_time,
+            input_audio,
+            output_audio,
+            input_events,
+            output_events,
+        )
+    }
+
+    /// See [`AudioProcessor::process_raw`].
+    ///
+    /// # Errors
+    ///
+    /// also errors with [`PluginError::NotProcessing`] while the audio processor is handed over to the main thread, in
+    /// which case the output buffers weren't written to
+    pub fn process_raw(
+        &mut self,
+        input_audio: &InputAudioBuffers<'_>,
+        output_audio: &mut OutputAudioBuffers<'_>,
+        input_events: &InputEvents<'_>,
+        output_events: &mut OutputEvents<'_>,
+    ) -> Result<ProcessStatus, PluginError> {
+        self.handle_commands();
+
+        let Some(processor) = &mut self.processor else {
+            return Err(PluginError::NotProcessing);
+        };
+
+        processor.process_raw(
+            &mut self.steady_time,
+            input_audio,
+            output_audio,
+            input_events,
+            output_events,
+        )
+    }
+}
+
+impl Drop for AudioThreadProcessor {
+    fn drop(&mut self) {
+        self.handle_commands();
+        self.hand_over();
+    }
+}
+
+/// The length of a block, which is as long as the shortest of its buffers.
+pub fn block_frames<T: Sample>(input_audio: &[&[&[T]]], output_audio: &[&mut [&mut [T]]]) -> usize {
+    input_audio
+        .iter()
+        .flat_map(|port| port.iter().map(|buffer| buffer.len()))
+        .chain(
+            output_audio
+                .iter()
+                .flat_map(|port| port.iter().map(|buffer| buffer.len())),
+        )
+        .min()
+        .unwrap_or(0)
+}
+
+/// Whether the first `frames` frames of every buffer are silent.
+fn is_silent<'a, T: Sample>(buffers: impl IntoIterator<Item = &'a [T]>, frames: usize) -> bool {
+    buffers.into_iter().all(|buffer| {
+        buffer[..frames]
+            .iter()
+            .all(|&sample| sample.to_f64() == 0.0)
+    })
+}
 
-        (output_audio_buffers, output_events_buffer)
+fn silence<T: Sample>(output_audio: &mut [&mut [&mut [T]]]) {
+    for buffer in output_audio.iter_mut().flat_map(|port| port.iter_mut()) {
+        buffer.fill(T::default());
     }
 }
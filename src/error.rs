@@ -0,0 +1,104 @@
+use clack_host::prelude::PluginInstanceError;
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result},
+};
+
+/// Reasons a plugin couldn't be started by [`run`](crate::run) or [`HostRuntime::run`](crate::HostRuntime::run).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostError {
+    /// the bundle doesn't expose a plugin factory
+    NoFactory,
+    /// the bundle's plugin factory has no plugin with this id
+    UnknownPluginId(String),
+    /// the bundle's plugin factory has no plugin at this index
+    UnknownPluginIndex(u32),
+    /// one of the [`HostConfig`](crate::HostConfig)'s strings contains a nul byte
+    InvalidHostConfig,
+    /// the plugin couldn't be instantiated
+    Instantiation(PluginInstanceError),
+    /// the plugin couldn't be activated
+    Activation(PluginInstanceError),
+    /// the plugin panicked while it was being started, and was abandoned
+    StartPanicked,
+    /// the [`HostRuntime`](crate::HostRuntime)'s main thread isn't running anymore
+    RuntimeGone,
+    /// the plugin process couldn't be spawned, or reported an error
+    PluginProcess(String),
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::NoFactory => write!(f, "the bundle doesn't expose a plugin factory"),
+            Self::UnknownPluginId(id) => write!(f, "the bundle contains no plugin with id {id:?}"),
+            Self::UnknownPluginIndex(index) => {
+                write!(f, "the bundle contains no plugin at index {index}")
+            }
+            Self::InvalidHostConfig => write!(f, "the host config contains a nul byte"),
+            Self::Instantiation(err) => write!(f, "the plugin couldn't be instantiated: {err}"),
+            Self::Activation(err) => write!(f, "the plugin couldn't be activated: {err}"),
+            Self::StartPanicked => write!(f, "the plugin panicked while starting"),
+            Self::RuntimeGone => write!(f, "the host runtime isn't running anymore"),
+            Self::PluginProcess(err) => write!(f, "the plugin process failed: {err}"),
+        }
+    }
+}
+
+impl Error for HostError {}
+
+/// Reasons a call on a [`ClapPlugin`](crate::ClapPlugin) failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PluginError {
+    /// the plugin isn't running anymore, because it was closed or panicked, or its runtime shut down
+    HostThreadGone,
+    /// the plugin didn't shut down in time, and was left running in the background
+    Unresponsive,
+    /// the plugin failed to process audio
+    Process(PluginInstanceError),
+    /// the plugin isn't processing right now, because it refused to start or is being reactivated
+    NotProcessing,
+    /// the plugin couldn't be activated again with the new configuration
+    Reconfigure(HostError),
+    /// the audio processor wasn't handed over to the plugin's main thread in time, because no block was processed and
+    /// the [`AudioHandle`](crate::AudioHandle) wasn't released
+    HandOverTimeout,
+    /// the plugin process crashed or stopped responding, holding the state the plugin last saved or loaded
+    Crashed(Option<Vec<u8>>),
+    /// the plugin process reported an error, or couldn't be communicated with
+    PluginProcess(String),
+    /// the plugin doesn't support the state extension
+    #[cfg(feature = "state")]
+    NoStateExtension,
+    /// the plugin failed to save its state
+    #[cfg(feature = "state")]
+    StateSave,
+    /// the plugin failed to load its state
+    #[cfg(feature = "state")]
+    StateLoad,
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::HostThreadGone => write!(f, "the plugin isn't running anymore"),
+            Self::Unresponsive => write!(f, "the plugin didn't shut down in time"),
+            Self::Process(err) => write!(f, "the plugin failed to process audio: {err}"),
+            Self::NotProcessing => write!(f, "the plugin isn't processing right now"),
+            Self::Reconfigure(err) => write!(f, "the plugin couldn't be reconfigured: {err}"),
+            Self::HandOverTimeout => {
+                write!(f, "the audio thread didn't hand the plugin over in time")
+            }
+            Self::Crashed(_) => write!(f, "the plugin process crashed"),
+            Self::PluginProcess(err) => write!(f, "the plugin process failed: {err}"),
+            #[cfg(feature = "state")]
+            Self::NoStateExtension => write!(f, "the plugin doesn't support saving its state"),
+            #[cfg(feature = "state")]
+            Self::StateSave => write!(f, "the plugin failed to save its state"),
+            #[cfg(feature = "state")]
+            Self::StateLoad => write!(f, "the plugin failed to load its state"),
+        }
+    }
+}
+
+impl Error for PluginError {}
@@ -0,0 +1,160 @@
+use crate::Host;
+use clack_extensions::audio_ports::{AudioPortFlags, AudioPortInfo, AudioPortInfoBuffer};
+use clack_host::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// One of a plugin's audio ports, as reported by its audio-ports extension.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[expect(clippy::struct_excessive_bools)]
+pub struct AudioPort {
+    pub name: String,
+    pub channel_count: u32,
+    /// e.g. `"mono"` or `"stereo"`, if the plugin says
+    pub port_type: Option<String>,
+    /// the port is the plugin's main input or output, rather than e.g. a sidechain or an aux bus
+    pub is_main: bool,
+    /// the port can be handed 64-bit buffers
+    pub supports_64bits: bool,
+    /// the port would rather be handed 64-bit buffers, even when the host processes 32-bit audio
+    pub prefers_64bits: bool,
+    /// all ports with this flag have to be handed buffers of the same sample format
+    pub requires_common_sample_size: bool,
+}
+
+impl From<AudioPortInfo<'_>> for AudioPort {
+    fn from(info: AudioPortInfo<'_>) -> Self {
+        Self {
+            name: String::from_utf8_lossy(info.name).into_owned(),
+            channel_count: info.channel_count,
+            port_type: info
+                .port_type
+                .map(|port_type| port_type.0.to_string_lossy().into_owned()),
+            is_main: info.flags.contains(AudioPortFlags::IS_MAIN),
+            supports_64bits: info.flags.contains(AudioPortFlags::SUPPORTS_64BITS),
+            prefers_64bits: info.flags.contains(AudioPortFlags::PREFERS_64BITS),
+            requires_common_sample_size: info
+                .flags
+                .contains(AudioPortFlags::REQUIRES_COMMON_SAMPLE_SIZE),
+        }
+    }
+}
+
+/// A plugin's audio ports, in the order their buffers are passed to
+/// [`AudioHandle::process_audio`](crate::AudioHandle::process_audio).
+///
+/// The ports only change while the plugin is deactivated, which it reports with
+/// [`RestartReason::AudioPortsChanged`](crate::RestartReason::AudioPortsChanged).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AudioPortLayout {
+    pub inputs: Vec<AudioPort>,
+    pub outputs: Vec<AudioPort>,
+}
+
+impl AudioPortLayout {
+    /// Plugins that don't support the audio-ports extension have no audio ports.
+    pub(crate) fn query(instance: &mut PluginInstance<Host>) -> Self {
+        let Some(audio_ports) = instance.access_handler(|h| h.audio_ports) else {
+            return Self::default();
+        };
+
+        let mut plugin = instance.plugin_handle();
+        let mut buffer = AudioPortInfoBuffer::new();
+
+        let mut ports = |is_input| {
+            (0..audio_ports.count(&mut plugin, is_input))
+                .filter_map(|index| {
+                    audio_ports
+                        .get(&mut plugin, index, is_input, &mut buffer)
+                        .map(AudioPort::from)
+                })
+                .collect()
+        };
+
+        Self {
+            inputs: ports(true),
+            outputs: ports(false),
+        }
+    }
+
+    /// Which input and output ports are handed 64-bit buffers, depending on whether the host processes 64-bit audio.
+    ///
+    /// Ports get 64-bit buffers if they support them, and either the host processes 64-bit audio or they prefer them.
+    /// Ports that require a common sample size only get them if all of those ports do.
+    pub(crate) fn f64_ports(&self, processing_f64: bool) -> (Vec<bool>, Vec<bool>) {
+        let wants_f64 =
+            |port: &AudioPort| port.supports_64bits && (processing_f64 || port.prefers_64bits);
+
+        let common_f64 = self
+            .inputs
+            .iter()
+            .chain(&self.outputs)
+            .filter(|port| port.requires_common_sample_size)
+            .all(wants_f64);
+
+        let negotiate = |ports: &[AudioPort]| {
+            ports
+                .iter()
+                .map(|port| wants_f64(port) && (common_f64 || !port.requires_common_sample_size))
+                .collect()
+        };
+
+        (negotiate(&self.inputs), negotiate(&self.outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(supports_64bits: bool, prefers_64bits: bool, requires_common: bool) -> AudioPort {
+        AudioPort {
+            name: String::new(),
+            channel_count: 2,
+            port_type: None,
+            is_main: true,
+            supports_64bits,
+            prefers_64bits,
+            requires_common_sample_size: requires_common,
+        }
+    }
+
+    #[test]
+    fn ports_get_f64_buffers_when_the_host_processes_f64() {
+        let layout = AudioPortLayout {
+            inputs: vec![port(true, false, false), port(false, false, false)],
+            outputs: vec![port(true, false, false)],
+        };
+
+        assert_eq!(layout.f64_ports(true), (vec![true, false], vec![true]));
+        assert_eq!(layout.f64_ports(false), (vec![false, false], vec![false]));
+    }
+
+    #[test]
+    fn ports_that_prefer_f64_get_it_either_way() {
+        let layout = AudioPortLayout {
+            inputs: vec![port(true, true, false)],
+            // preferring 64-bit buffers without supporting them doesn't count
+            outputs: vec![port(false, true, false)],
+        };
+
+        assert_eq!(layout.f64_ports(false), (vec![true], vec![false]));
+        assert_eq!(layout.f64_ports(true), (vec![true], vec![false]));
+    }
+
+    #[test]
+    fn common_sample_size_falls_back_to_f32() {
+        let layout = AudioPortLayout {
+            inputs: vec![port(true, false, true), port(true, false, false)],
+            outputs: vec![port(false, false, true)],
+        };
+
+        assert_eq!(layout.f64_ports(true), (vec![false, true], vec![false]));
+
+        let layout = AudioPortLayout {
+            inputs: vec![port(true, false, true)],
+            outputs: vec![port(true, false, true)],
+        };
+
+        assert_eq!(layout.f64_ports(true), (vec![true], vec![true]));
+    }
+}
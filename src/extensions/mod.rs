@@ -1,6 +1,6 @@
+#[cfg(feature = "audio-ports")]
+pub mod audio_ports;
 #[cfg(feature = "gui")]
 pub mod gui;
-#[cfg(not(feature = "gui"))]
-pub mod no_gui;
 #[cfg(feature = "timer")]
 pub mod timer;
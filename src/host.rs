@@ -12,15 +12,35 @@ use clack_extensions::state::HostState;
 #[cfg(feature = "timer")]
 use clack_extensions::timer::HostTimer;
 use clack_host::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub struct Host;
 
-#[derive(Debug)]
-pub enum HostThreadMessage {
-    AudioProcessed(Vec<Vec<f32>>, EventBuffer),
-    Counter(u64),
-    #[cfg(feature = "state")]
-    State(Vec<u8>),
+/// How the host identifies itself to plugins.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostConfig {
+    pub name: String,
+    pub vendor: String,
+    pub url: String,
+    pub version: String,
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME").to_owned(),
+            vendor: "generic-daw".to_owned(),
+            url: "https://github.com/generic-daw/generic-clap-host".to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+}
+
+impl HostConfig {
+    pub(crate) fn host_info(&self) -> Result<HostInfo, crate::HostError> {
+        HostInfo::new(&self.name, &self.vendor, &self.url, &self.version)
+            .map_err(|_| crate::HostError::InvalidHostConfig)
+    }
 }
 
 impl HostHandlers for Host {
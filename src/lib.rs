@@ -1,238 +1,92 @@
 use audio_processor::AudioProcessor;
 pub use clack_host;
 use clack_host::prelude::*;
-use etcetera::{choose_base_strategy, BaseStrategy as _};
-#[cfg(feature = "gui")]
-use extensions::gui::GuiExt;
-#[cfg(not(feature = "gui"))]
-use extensions::no_gui::run_no_gui;
-use host::{Host, HostThreadMessage};
+pub use error::{HostError, PluginError};
+#[cfg(feature = "audio-ports")]
+pub use extensions::audio_ports::{AudioPort, AudioPortLayout};
+use host::Host;
+pub use host::HostConfig;
 use main_thread::{MainThread, MainThreadMessage};
-use shared::Shared;
-use std::{
-    cell::UnsafeCell,
-    marker::PhantomData,
-    path::PathBuf,
-    result::Result,
-    sync::mpsc::{Receiver, Sender},
+pub use notification::{PluginNotification, RestartReason};
+#[cfg(feature = "out-of-process")]
+pub use out_of_process::{run_out_of_process, run_plugin_worker};
+pub use plugin::{AudioHandle, ClapPlugin, ControlHandle};
+pub use runtime::HostRuntime;
+pub use scanner::{
+    get_installed_plugins, run_scan_worker, scan_installed_plugins, PluginCatalog, PluginFeature,
+    PluginInfo, PluginScanner, Sandbox, ScanCache, ScanEntry, ScanEvent, ScanHandle, ScanOutcome,
+    ScanReport, ShadowedPlugin,
 };
-use walkdir::WalkDir;
+#[cfg(feature = "watch")]
+pub use scanner::{PluginWatcher, WatchEvent};
+use serde::{Deserialize, Serialize};
+use shared::Shared;
+use std::ffi::{CStr, CString};
 
+mod audio_buffers;
 pub(crate) mod audio_processor;
+mod error;
 mod extensions;
 pub(crate) mod host;
 pub(crate) mod main_thread;
+mod notification;
+#[cfg(feature = "out-of-process")]
+mod out_of_process;
+mod plugin;
+mod runtime;
+mod scanner;
 mod shared;
 
-#[derive(Debug)]
-pub struct ClapPlugin {
-    sender: Sender<MainThreadMessage>,
-    receiver: Receiver<HostThreadMessage>,
-    _no_sync: PhantomData<UnsafeCell<()>>,
+/// Which of the plugins in a bundle to instantiate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PluginSelector {
+    /// the plugin's CLAP id
+    Id(String),
+    /// the index of the plugin's descriptor in the bundle's plugin factory
+    Index(u32),
 }
 
-impl ClapPlugin {
-    fn new(sender: Sender<MainThreadMessage>, receiver: Receiver<HostThreadMessage>) -> Self {
-        Self {
-            sender,
-            receiver,
-            _no_sync: PhantomData,
-        }
-    }
-
-    /// # Panics
-    ///
-    /// This will never panic, since this function blocks until the audio is processed, and you can't share the `ClapPlugin` between threads.
-    #[must_use]
-    pub fn process_audio(
-        &self,
-        input_audio: Vec<Vec<f32>>,
-        input_audio_ports: AudioPorts,
-        output_audio_ports: AudioPorts,
-        input_events: EventBuffer,
-    ) -> (Vec<Vec<f32>>, EventBuffer) {
-        self.sender
-            .send(MainThreadMessage::ProcessAudio(
-                input_audio,
-                input_audio_ports,
-                output_audio_ports,
-                input_events,
-            ))
-            .unwrap();
-
-        match self.receiver.recv() {
-            Ok(HostThreadMessage::AudioProcessed(output_audio, output_events)) => {
-                (output_audio, output_events)
-            }
-            _ => unreachable!(),
-        }
-    }
-
-    /// # Panics
-    ///
-    /// This will never panic, since this function blocks until the counter is fetched, and you can't share the `ClapPlugin` between threads.
-    #[must_use]
-    pub fn get_counter(&self) -> u64 {
-        self.sender.send(MainThreadMessage::GetCounter).unwrap();
-
-        match self.receiver.recv() {
-            Ok(HostThreadMessage::Counter(counter)) => counter,
-            _ => unreachable!(),
-        }
+impl From<&PluginInfo> for PluginSelector {
+    fn from(plugin: &PluginInfo) -> Self {
+        Self::Id(plugin.id.clone())
     }
-
-    /// # Panics
-    ///
-    /// This will never panic, since this function blocks until the state is fetched, and you can't share the `ClapPlugin` between threads.
-    #[must_use]
-    #[cfg(feature = "state")]
-    pub fn get_state(&self) -> Vec<u8> {
-        self.sender.send(MainThreadMessage::GetState).unwrap();
-
-        match self.receiver.recv() {
-            Ok(HostThreadMessage::State(state)) => state,
-            _ => unreachable!(),
-        }
-    }
-
-    /// # Panics
-    ///
-    /// This will never panic
-    #[cfg(feature = "state")]
-    pub fn set_state(&self, state: Vec<u8>) {
-        self.sender
-            .send(MainThreadMessage::SetState(state))
-            .unwrap();
-    }
-}
-
-#[must_use]
-pub fn get_installed_plugins() -> Vec<PluginBundle> {
-    standard_clap_paths()
-        .iter()
-        .flat_map(|path| {
-            WalkDir::new(path)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|dir_entry| dir_entry.file_type().is_file())
-                .filter(|dir_entry| {
-                    dir_entry
-                        .path()
-                        .extension()
-                        .is_some_and(|ext| ext == "clap")
-                })
-        })
-        .filter_map(|path| unsafe { PluginBundle::load(path.path()) }.ok())
-        .filter(|bundle| {
-            bundle
-                .get_plugin_factory()
-                .is_some_and(|factory| factory.plugin_descriptors().next().is_some())
-        })
-        .collect()
 }
 
-fn standard_clap_paths() -> Vec<PathBuf> {
-    let strategy = choose_base_strategy().unwrap();
-
-    let mut paths = vec![];
-
-    paths.push(strategy.home_dir().join(".clap"));
-
-    #[cfg(target_os = "windows")]
-    {
-        if let Some(val) = std::env::var_os("CommonProgramFiles") {
-            paths.push(PathBuf::from(val).join("CLAP"));
+impl PluginSelector {
+    fn resolve(&self, bundle: &PluginBundle) -> Result<CString, HostError> {
+        let factory = bundle.get_plugin_factory().ok_or(HostError::NoFactory)?;
+
+        match self {
+            Self::Id(id) => factory
+                .plugin_descriptors()
+                .filter_map(|descriptor| descriptor.id())
+                .find(|plugin_id| plugin_id.to_string_lossy() == id.as_str())
+                .map(CStr::to_owned)
+                .ok_or_else(|| HostError::UnknownPluginId(id.clone())),
+            Self::Index(index) => factory
+                .plugin_descriptors()
+                .nth(*index as usize)
+                .and_then(|descriptor| descriptor.id())
+                .map(CStr::to_owned)
+                .ok_or(HostError::UnknownPluginIndex(*index)),
         }
-
-        paths.push(strategy.config_dir().join("Programs\\Common\\CLAP"));
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        paths.push(strategy.home_dir().join("Library/Audio/Plug-Ins/CLAP"));
-
-        paths.push(PathBuf::from("/Library/Audio/Plug-Ins/CLAP"));
     }
-
-    #[cfg(target_family = "unix")]
-    paths.push("/usr/lib/clap".into());
-
-    if let Some(env_var) = std::env::var_os("CLAP_PATH") {
-        paths.extend(std::env::split_paths(&env_var));
-    }
-
-    paths
 }
 
-/// # Panics
+/// Instantiates the plugin chosen by `plugin` from `bundle` on the [global runtime](HostRuntime::global) and starts
+/// processing audio.
 ///
-/// panics if the plugin doesn't expose a `PluginFactory`
-#[must_use]
-pub fn run(bundle: PluginBundle, config: PluginAudioConfiguration) -> ClapPlugin {
-    let (sender_plugin, receiver_plugin) = std::sync::mpsc::channel();
-    let (sender_host, receiver_host) = std::sync::mpsc::channel();
-
-    let sender_plugin_clone = sender_plugin.clone();
-
-    std::thread::spawn(move || {
-        let factory = bundle.get_plugin_factory().unwrap();
-        let plugin_descriptor = factory.plugin_descriptors().next().unwrap();
-        let mut instance = PluginInstance::<Host>::new(
-            |()| Shared::new(sender_plugin_clone),
-            |shared| {
-                #[cfg(not(feature = "state"))]
-                let _ = shared;
-
-                MainThread::new(
-                    #[cfg(feature = "state")]
-                    shared,
-                )
-            },
-            &bundle,
-            plugin_descriptor.id().unwrap(),
-            &HostInfo::new("", "", "", "").unwrap(),
-        )
-        .unwrap();
-
-        let audio_processor = instance
-            .activate(|_, _| {}, config)
-            .unwrap()
-            .start_processing()
-            .unwrap();
-
-        #[cfg(not(feature = "gui"))]
-        run_no_gui(
-            instance,
-            &sender_host,
-            &receiver_plugin,
-            &mut AudioProcessor::new(audio_processor),
-        );
-
-        #[cfg(feature = "gui")]
-        {
-            let mut gui = instance
-                .access_handler(|h| h.gui)
-                .map(|gui| GuiExt::new(gui, &mut instance.plugin_handle()))
-                .unwrap();
-
-            if gui.needs_floating().unwrap() {
-                gui.run_gui_floating(
-                    instance,
-                    &sender_host,
-                    &receiver_plugin,
-                    &mut AudioProcessor::new(audio_processor),
-                );
-            } else {
-                gui.run_gui_embedded(
-                    instance,
-                    &sender_host,
-                    &receiver_plugin,
-                    &mut AudioProcessor::new(audio_processor),
-                );
-            }
-        }
-    });
-
-    ClapPlugin::new(sender_plugin, receiver_host)
+/// The plugin is told it's being hosted by the host described in `host_config`.
+///
+/// # Errors
+///
+/// errors if the bundle doesn't expose a plugin factory, doesn't contain the requested plugin, if `host_config` isn't
+/// valid, or if the plugin fails to instantiate or activate, or panics while starting
+pub fn run(
+    bundle: PluginBundle,
+    plugin: &PluginSelector,
+    host_config: &HostConfig,
+    config: PluginAudioConfiguration,
+) -> Result<ClapPlugin, HostError> {
+    HostRuntime::global().run(bundle, plugin, host_config, config)
 }
@@ -1,9 +1,15 @@
+#[cfg(feature = "audio-ports")]
+use crate::extensions::audio_ports::AudioPortLayout;
 #[cfg(feature = "timer")]
 use crate::extensions::timer::Timers;
-#[cfg(feature = "state")]
-use crate::shared::Shared;
+use crate::{
+    audio_processor::{AudioProcessor, MainThreadProcessor},
+    runtime::Waker,
+    shared::Shared,
+    Host, PluginError, PluginNotification, RestartReason,
+};
 #[cfg(feature = "audio-ports")]
-use clack_extensions::audio_ports::{HostAudioPortsImpl, RescanType};
+use clack_extensions::audio_ports::{HostAudioPortsImpl, PluginAudioPorts, RescanType};
 #[cfg(feature = "gui")]
 use clack_extensions::gui::{GuiSize, PluginGui};
 #[cfg(feature = "log")]
@@ -13,33 +19,212 @@ use clack_extensions::note_ports::{HostNotePortsImpl, NoteDialects, NotePortResc
 #[cfg(feature = "params")]
 use clack_extensions::params::{HostParamsImplMainThread, ParamClearFlags, ParamRescanFlags};
 #[cfg(feature = "state")]
-use clack_extensions::state::HostStateImpl;
+use clack_extensions::state::{HostStateImpl, PluginState};
 #[cfg(feature = "timer")]
 use clack_extensions::timer::{HostTimerImpl, PluginTimer, TimerId};
 use clack_host::prelude::*;
+#[cfg(feature = "state")]
+use std::io::Cursor;
 #[cfg(feature = "timer")]
-use std::{rc::Rc, time::Duration};
+use std::rc::Rc;
+use std::{
+    sync::mpsc::{SendError, Sender},
+    time::{Duration, Instant},
+};
 #[cfg(feature = "log")]
 use tracing::{debug, error, info, warn};
 
+/// how long requests that need the audio processor wait for the audio thread to hand it over, before they fail
+pub const HAND_OVER_TIMEOUT: Duration = Duration::from_millis(500);
+
 pub enum MainThreadMessage {
     RunOnMainThread,
     #[cfg(feature = "gui")]
     GuiClosed,
     #[cfg(feature = "gui")]
     GuiRequestResized(GuiSize),
-    ProcessAudio(Vec<Vec<f32>>, AudioPorts, AudioPorts, EventBuffer),
-    GetCounter,
+    Reconfigure(PluginAudioConfiguration, Sender<Result<(), PluginError>>),
+    Restart,
+    RequestProcess,
+    Close(Sender<Result<(), PluginError>>),
+    #[cfg(feature = "audio-ports")]
+    GetAudioPorts(Sender<Result<AudioPortLayout, PluginError>>),
     #[cfg(feature = "state")]
-    GetState,
+    GetState(Sender<Result<Vec<u8>, PluginError>>),
     #[cfg(feature = "state")]
-    SetState(Vec<u8>),
+    SetState(Vec<u8>, Sender<Result<(), PluginError>>),
+}
+
+/// Sends messages to a plugin's main thread, and wakes the thread up to handle them.
+#[derive(Clone, Debug)]
+pub struct MainThreadSender {
+    sender: Sender<MainThreadMessage>,
+    /// `None` if the main thread waits on the receiving end of `sender` itself
+    waker: Option<Waker>,
+}
+
+impl MainThreadSender {
+    pub const fn new(sender: Sender<MainThreadMessage>, waker: Option<Waker>) -> Self {
+        Self { sender, waker }
+    }
+
+    pub fn send(&self, message: MainThreadMessage) -> Result<(), SendError<MainThreadMessage>> {
+        self.sender.send(message)?;
+
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+}
+
+/// Requests that reactivate the plugin, which wait until the audio thread hands the audio processor over.
+pub enum Deferred {
+    /// fails with [`PluginError::HandOverTimeout`] if the audio processor isn't handed over by the deadline
+    Reconfigure(
+        PluginAudioConfiguration,
+        Sender<Result<(), PluginError>>,
+        Instant,
+    ),
+    Restart,
+}
+
+/// Handles the messages that don't depend on whether the plugin's GUI is open.
+///
+/// GUI messages are ignored, so main thread loops that manage a GUI should handle them before calling this. Every main
+/// thread loop should handle [`MainThreadMessage::Close`] by closing the plugin. Requests that reactivate the plugin
+/// are added to `deferred`, and should be passed to [`handle_deferred`] once the audio thread hands the audio
+/// processor over.
+///
+/// Replies are sent on the channel that came with the request. Whoever sent it may have stopped waiting, so failing to
+/// reply isn't an error.
+pub fn handle_message(
+    instance: &mut PluginInstance<Host>,
+    message: MainThreadMessage,
+    audio_processor: &mut MainThreadProcessor,
+    deferred: &mut Vec<Deferred>,
+) {
+    match message {
+        MainThreadMessage::RunOnMainThread => instance.call_on_main_thread_callback(),
+        MainThreadMessage::Reconfigure(config, reply) => {
+            deferred.push(Deferred::Reconfigure(
+                config,
+                reply,
+                Instant::now() + HAND_OVER_TIMEOUT,
+            ));
+        }
+        MainThreadMessage::Restart => deferred.push(Deferred::Restart),
+        MainThreadMessage::RequestProcess => audio_processor.request_process(),
+        #[cfg(feature = "audio-ports")]
+        MainThreadMessage::GetAudioPorts(reply) => {
+            reply.send(Ok(AudioPortLayout::query(instance))).ok();
+        }
+        #[cfg(feature = "state")]
+        MainThreadMessage::GetState(reply) => {
+            let result = state_ext(instance).and_then(|state_ext| {
+                let mut state = Vec::new();
+                state_ext
+                    .save(&mut instance.plugin_handle(), &mut state)
+                    .map_err(|_| PluginError::StateSave)?;
+                Ok(state)
+            });
+
+            reply.send(result).ok();
+        }
+        #[cfg(feature = "state")]
+        MainThreadMessage::SetState(state, reply) => {
+            let result = state_ext(instance).and_then(|state_ext| {
+                state_ext
+                    .load(&mut instance.plugin_handle(), &mut Cursor::new(state))
+                    .map_err(|_| PluginError::StateLoad)
+            });
+
+            reply.send(result).ok();
+        }
+        #[cfg(feature = "gui")]
+        MainThreadMessage::GuiClosed | MainThreadMessage::GuiRequestResized(_) => {}
+        MainThreadMessage::Close(_) => {}
+    }
+}
+
+/// Handles the requests [`handle_message`] deferred, now that the audio thread handed the audio processor over.
+pub fn handle_deferred(
+    instance: &mut PluginInstance<Host>,
+    audio_processor: &mut AudioProcessor,
+    deferred: &mut Vec<Deferred>,
+) {
+    for request in deferred.drain(..) {
+        match request {
+            Deferred::Reconfigure(config, reply, _) => {
+                let result = audio_processor
+                    .reconfigure(instance, config)
+                    .map_err(PluginError::Reconfigure);
+
+                reply.send(result).ok();
+            }
+            Deferred::Restart => {
+                // cleared first, so that a restart the plugin requests while it's restarting isn't lost
+                instance.access_shared_handler(Shared::restarting);
+
+                // reasons the plugin reports while it's restarting are merged in, so that none of them are lost
+                let mut reasons =
+                    instance.access_handler_mut(|h| std::mem::take(&mut h.restart_reasons));
+
+                let restarted = audio_processor.restart(instance);
+
+                instance.access_handler_mut(|h| {
+                    for reason in std::mem::take(&mut h.restart_reasons) {
+                        if !reasons.contains(&reason) {
+                            reasons.push(reason);
+                        }
+                    }
+                });
+
+                let notification = match restarted {
+                    Ok(()) => PluginNotification::Restarted(reasons),
+                    Err(err) => {
+                        // the owner is told what changed once a later restart succeeds
+                        instance.access_handler_mut(|h| h.restart_reasons = reasons);
+                        PluginNotification::RestartFailed(err)
+                    }
+                };
+
+                instance.access_shared_handler(|s| s.notify(notification));
+            }
+        }
+    }
+}
+
+/// Fails the requests [`handle_message`] deferred that waited for the audio processor past their deadline, e.g.
+/// because the audio thread stopped processing without releasing it.
+///
+/// Restarts are kept, since the plugin asked for them and nobody is waiting.
+pub fn expire_deferred(deferred: &mut Vec<Deferred>) {
+    let now = Instant::now();
+
+    deferred.retain(|request| match request {
+        Deferred::Reconfigure(_, reply, deadline) if *deadline <= now => {
+            reply.send(Err(PluginError::HandOverTimeout)).ok();
+            false
+        }
+        _ => true,
+    });
+}
+
+#[cfg(feature = "state")]
+fn state_ext(instance: &PluginInstance<Host>) -> Result<PluginState, PluginError> {
+    instance
+        .access_handler(|h| h.shared.state.get().copied().flatten())
+        .ok_or(PluginError::NoStateExtension)
 }
 
 pub struct MainThread<'a> {
     #[cfg(feature = "state")]
     pub shared: &'a Shared,
     plugin: Option<InitializedPluginHandle<'a>>,
+    #[cfg(feature = "audio-ports")]
+    pub audio_ports: Option<PluginAudioPorts>,
     #[cfg(feature = "gui")]
     pub gui: Option<PluginGui>,
     #[cfg(feature = "timer")]
@@ -48,6 +233,7 @@ pub struct MainThread<'a> {
     pub timers: Rc<Timers>,
     #[cfg(feature = "state")]
     pub dirty: bool,
+    pub restart_reasons: Vec<RestartReason>,
 }
 
 #[cfg(not(feature = "state"))]
@@ -55,12 +241,15 @@ impl MainThread<'_> {
     pub fn new() -> Self {
         Self {
             plugin: None,
+            #[cfg(feature = "audio-ports")]
+            audio_ports: None,
             #[cfg(feature = "gui")]
             gui: None,
             #[cfg(feature = "timer")]
             timer_support: None,
             #[cfg(feature = "timer")]
             timers: Rc::default(),
+            restart_reasons: Vec::new(),
         }
     }
 }
@@ -71,6 +260,8 @@ impl<'a> MainThread<'a> {
         Self {
             shared,
             plugin: None,
+            #[cfg(feature = "audio-ports")]
+            audio_ports: None,
             #[cfg(feature = "gui")]
             gui: None,
             #[cfg(feature = "timer")]
@@ -78,12 +269,17 @@ impl<'a> MainThread<'a> {
             #[cfg(feature = "timer")]
             timers: Rc::default(),
             dirty: false,
+            restart_reasons: Vec::new(),
         }
     }
 }
 
 impl<'a> MainThreadHandler<'a> for MainThread<'a> {
     fn initialized(&mut self, instance: InitializedPluginHandle<'a>) {
+        #[cfg(feature = "audio-ports")]
+        {
+            self.audio_ports = instance.get_extension();
+        }
         #[cfg(feature = "gui")]
         {
             self.gui = instance.get_extension();
@@ -97,14 +293,33 @@ impl<'a> MainThreadHandler<'a> for MainThread<'a> {
     }
 }
 
+impl MainThread<'_> {
+    #[cfg_attr(
+        not(any(feature = "audio-ports", feature = "note-ports")),
+        expect(dead_code)
+    )]
+    fn changed(&mut self, reason: RestartReason) {
+        if !self.restart_reasons.contains(&reason) {
+            self.restart_reasons.push(reason);
+        }
+    }
+}
+
 #[cfg(feature = "audio-ports")]
 impl HostAudioPortsImpl for MainThread<'_> {
-    fn is_rescan_flag_supported(&self, _flag: RescanType) -> bool {
-        false
+    fn is_rescan_flag_supported(&self, flag: RescanType) -> bool {
+        // the ports are queried again after every restart, but in-place pairs aren't used
+        (RescanType::NAMES
+            | RescanType::FLAGS
+            | RescanType::CHANNEL_COUNT
+            | RescanType::PORT_TYPE
+            | RescanType::LIST)
+            .contains(flag)
     }
 
     fn rescan(&mut self, _flag: RescanType) {
-        // we don't support audio ports changing on the fly (yet)
+        // the ports can only change while the plugin is deactivated, so the owner is told after the next restart
+        self.changed(RestartReason::AudioPortsChanged);
     }
 }
 
@@ -130,7 +345,7 @@ impl HostNotePortsImpl for MainThread<'_> {
     }
 
     fn rescan(&mut self, _flags: NotePortRescanFlags) {
-        // We don't support note ports changing on the fly (yet)
+        self.changed(RestartReason::NotePortsChanged);
     }
 }
 
@@ -0,0 +1,23 @@
+use crate::HostError;
+use serde::{Deserialize, Serialize};
+
+/// Things that happened to a plugin without its [`ClapPlugin`](crate::ClapPlugin) asking for them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PluginNotification {
+    /// the plugin asked to be restarted, and was deactivated and activated again
+    ///
+    /// lists what the plugin reported as changed while it was deactivated, which is empty if it didn't say
+    Restarted(Vec<RestartReason>),
+    /// the plugin asked to be restarted, but couldn't be activated again, so it outputs silence until it's successfully
+    /// reconfigured
+    RestartFailed(HostError),
+}
+
+/// Changes a plugin reported while it was being restarted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RestartReason {
+    /// the plugin's audio ports changed, only reported with the `audio-ports` feature
+    AudioPortsChanged,
+    /// the plugin's note ports changed, only reported with the `note-ports` feature
+    NotePortsChanged,
+}
@@ -0,0 +1,526 @@
+#[cfg(feature = "audio-ports")]
+use crate::AudioPortLayout;
+use crate::{
+    audio_buffers::{convert, Sample},
+    audio_processor::block_frames,
+    main_thread::MainThreadSender,
+    AudioHandle, ClapPlugin, ControlHandle, HostConfig, HostError, MainThreadMessage, PluginError,
+    PluginNotification, PluginSelector, Sandbox,
+};
+use clack_host::prelude::*;
+use protocol::{
+    decode_events, encode_events, write_message, Notification, Request, Response, RESPONSE_MARKER,
+};
+use shared_audio::SharedAudio;
+use std::{
+    io::{self, BufRead as _, BufReader},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex, MutexGuard, PoisonError, Weak,
+    },
+    thread,
+    time::Duration,
+};
+pub use worker::run_plugin_worker;
+
+mod protocol;
+mod shared_audio;
+mod worker;
+
+/// set on plugin worker processes, holds the path of the bundle to load
+const BUNDLE_ENV: &str = "GENERIC_CLAP_HOST_PLUGIN_BUNDLE";
+/// set on plugin worker processes, holds the path of the file backing the shared audio buffers
+const AUDIO_ENV: &str = "GENERIC_CLAP_HOST_PLUGIN_AUDIO";
+
+/// Like [`run`](crate::run), but hosts the plugin in a child process, so that the plugin crashing can't take the host
+/// down.
+///
+/// The child process is the `sandbox`'s program, which has to call [`run_plugin_worker`] at the start of its `main`
+/// function. Audio, events and state are exchanged through shared memory, everything else through the child's stdin
+/// and stdout.
+///
+/// If the plugin process crashes, or doesn't answer within the `sandbox`'s timeout, every further call on the returned
+/// [`ClapPlugin`] errors with [`PluginError::Crashed`]. It holds the state the plugin last saved or loaded, so that it
+/// can be loaded into a new instance.
+///
+/// # Errors
+///
+/// errors if the plugin process can't be spawned, or fails to start the plugin
+///
+/// # Panics
+///
+/// This will never panic, since the plugin process' stdin and stdout are always piped.
+pub fn run_out_of_process(
+    bundle_path: &Path,
+    plugin: &PluginSelector,
+    host_config: &HostConfig,
+    config: PluginAudioConfiguration,
+    sandbox: &Sandbox,
+) -> Result<ClapPlugin, HostError> {
+    let process_error = |err: io::Error| HostError::PluginProcess(err.to_string());
+
+    let audio = SharedAudio::create().map_err(process_error)?;
+
+    let mut child = Command::new(sandbox.program())
+        .env(BUNDLE_ENV, bundle_path)
+        .env(AUDIO_ENV, audio.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(process_error)?;
+
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    let (sender_responses, receiver_responses) = std::sync::mpsc::channel();
+
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let Some(response) = line
+                .strip_prefix(RESPONSE_MARKER)
+                .and_then(|json| serde_json::from_str(json).ok())
+            else {
+                continue;
+            };
+
+            if sender_responses.send(response).is_err() {
+                return;
+            }
+        }
+    });
+
+    let (sender_plugin, receiver_plugin) = std::sync::mpsc::channel();
+    let (sender_audio, receiver_audio) = std::sync::mpsc::channel();
+    let (sender_audio_responses, receiver_audio_responses) = std::sync::mpsc::channel();
+    let (sender_notifications, receiver_notifications) = std::sync::mpsc::channel();
+
+    let alive = Arc::new(());
+
+    let mut proxy = Proxy {
+        child,
+        stdin,
+        responses: receiver_responses,
+        notifier: sender_notifications,
+        audio,
+        timeout: sandbox.timeout(),
+        last_state: None,
+        crashed: false,
+    };
+
+    match proxy.request(&Request::Start {
+        plugin: plugin.clone(),
+        host_config: host_config.clone(),
+        config: config.into(),
+    }) {
+        Ok(Response::Started(Ok(()))) => {}
+        Ok(Response::Started(Err(err))) => {
+            proxy.kill();
+            return Err(HostError::PluginProcess(err));
+        }
+        Ok(_) => {
+            proxy.kill();
+            return Err(HostError::PluginProcess(unexpected_response()));
+        }
+        Err(_) => {
+            return Err(HostError::PluginProcess(
+                "the plugin process exited before starting the plugin".to_owned(),
+            ))
+        }
+    }
+
+    let proxy = Arc::new(Mutex::new(proxy));
+
+    thread::spawn({
+        let proxy = proxy.clone();
+        let alive = Arc::downgrade(&alive);
+
+        move || Proxy::run_audio(&proxy, &sender_audio_responses, &receiver_audio, &alive)
+    });
+
+    let audio = RemoteAudio {
+        sender: sender_audio,
+        receiver: receiver_audio_responses,
+    };
+
+    let plugin = ClapPlugin::new(
+        AudioHandle::remote(audio, &alive),
+        ControlHandle::new(
+            MainThreadSender::new(sender_plugin, None),
+            receiver_notifications,
+            &alive,
+        ),
+    );
+
+    thread::spawn(move || Proxy::run_control(&proxy, &receiver_plugin, alive));
+
+    Ok(plugin)
+}
+
+enum AudioRequest {
+    /// the input channels of every port one after the other, how many channels each input and output port has, the
+    /// length of the block, and the input events
+    Process(Vec<Vec<f32>>, Vec<usize>, Vec<usize>, usize, EventBuffer),
+    GetCounter,
+}
+
+enum AudioResponse {
+    Processed(Result<(Vec<Vec<f32>>, EventBuffer), PluginError>),
+    Counter(Result<u64, PluginError>),
+}
+
+/// The audio side of a plugin hosted out of process, which hands every block to the proxy's audio thread.
+pub struct RemoteAudio {
+    sender: Sender<AudioRequest>,
+    receiver: Receiver<AudioResponse>,
+}
+
+impl RemoteAudio {
+    fn request(&self, request: AudioRequest) -> Result<AudioResponse, PluginError> {
+        self.sender
+            .send(request)
+            .map_err(|_| PluginError::HostThreadGone)?;
+
+        self.receiver
+            .recv()
+            .map_err(|_| PluginError::HostThreadGone)
+    }
+
+    /// The block is as long as the shortest of the input and output buffers, so that e.g. instruments without inputs
+    /// still get whole blocks. 64-bit audio is converted, since the plugin process always processes 32-bit audio.
+    pub fn process<T: Sample>(
+        &self,
+        input_audio: &[&[&[T]]],
+        output_audio: &mut [&mut [&mut [T]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        let mut events = EventBuffer::with_capacity(input_events.len());
+        for event in input_events {
+            events.push(event);
+        }
+
+        let frames = block_frames(input_audio, output_audio);
+
+        match self.request(AudioRequest::Process(
+            input_channels(input_audio, frames),
+            input_audio.iter().map(|port| port.len()).collect(),
+            output_audio.iter().map(|port| port.len()).collect(),
+            frames,
+            events,
+        ))? {
+            AudioResponse::Processed(result) => {
+                let (processed_audio, processed_events) = result?;
+
+                for (output, processed) in output_audio
+                    .iter_mut()
+                    .flat_map(|port| port.iter_mut())
+                    .zip(&processed_audio)
+                {
+                    convert(processed, output);
+                }
+
+                output_events.clear();
+                for event in &processed_events {
+                    output_events.push(event);
+                }
+
+                Ok(())
+            }
+            AudioResponse::Counter(_) => unreachable!(),
+        }
+    }
+
+    pub fn get_counter(&self) -> Result<u64, PluginError> {
+        match self.request(AudioRequest::GetCounter)? {
+            AudioResponse::Counter(result) => result,
+            AudioResponse::Processed(_) => unreachable!(),
+        }
+    }
+}
+
+/// The first `frames` frames of the input channels of every port one after the other, as 32-bit audio.
+fn input_channels<T: Sample>(input_audio: &[&[&[T]]], frames: usize) -> Vec<Vec<f32>> {
+    input_audio
+        .iter()
+        .flat_map(|port| {
+            port.iter().map(|buffer| {
+                buffer[..frames]
+                    .iter()
+                    .map(|&sample| sample.to_f32())
+                    .collect()
+            })
+        })
+        .collect()
+}
+
+fn unexpected_response() -> String {
+    "the plugin process sent an unexpected response".to_owned()
+}
+
+#[expect(clippy::needless_pass_by_value)]
+fn shared_memory_error(err: io::Error) -> PluginError {
+    PluginError::PluginProcess(err.to_string())
+}
+
+/// Stands in for the main thread of an in-process plugin, forwarding messages to the plugin process.
+struct Proxy {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<Response>,
+    notifier: Sender<PluginNotification>,
+    audio: SharedAudio,
+    timeout: Duration,
+    /// the state the plugin last saved or loaded
+    last_state: Option<Vec<u8>>,
+    crashed: bool,
+}
+
+impl Proxy {
+    /// Handles the requests of the plugin's [`RemoteAudio`], until either the handle is dropped or the plugin is
+    /// closed.
+    fn run_audio(
+        proxy: &Mutex<Self>,
+        sender: &Sender<AudioResponse>,
+        receiver: &Receiver<AudioRequest>,
+        alive: &Weak<()>,
+    ) {
+        while let Ok(message) = receiver.recv() {
+            // returning drops `sender`, which tells the audio handle that the plugin is gone
+            if alive.strong_count() == 0 {
+                return;
+            }
+
+            let response = {
+                let mut proxy = Self::lock(proxy);
+
+                match message {
+                    AudioRequest::Process(input_audio, inputs, outputs, frames, input_events) => {
+                        AudioResponse::Processed(proxy.process(
+                            &input_audio,
+                            inputs,
+                            outputs,
+                            frames,
+                            &input_events,
+                        ))
+                    }
+                    AudioRequest::GetCounter => AudioResponse::Counter(proxy.get_counter()),
+                }
+            };
+
+            if sender.send(response).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Handles the requests of the plugin's [`ControlHandle`]s, until the plugin is closed.
+    ///
+    /// `alive` is dropped once the plugin process is gone.
+    fn run_control(proxy: &Mutex<Self>, receiver: &Receiver<MainThreadMessage>, alive: Arc<()>) {
+        let close_reply = loop {
+            let message = receiver.recv();
+
+            match message {
+                Ok(MainThreadMessage::Reconfigure(config, reply)) => {
+                    let result = Self::lock(proxy).reconfigure(config);
+                    reply.send(result).ok();
+                }
+                #[cfg(feature = "audio-ports")]
+                Ok(MainThreadMessage::GetAudioPorts(reply)) => {
+                    let result = Self::lock(proxy).get_audio_ports();
+                    reply.send(result).ok();
+                }
+                #[cfg(feature = "state")]
+                Ok(MainThreadMessage::GetState(reply)) => {
+                    let result = Self::lock(proxy).get_state();
+                    reply.send(result).ok();
+                }
+                #[cfg(feature = "state")]
+                Ok(MainThreadMessage::SetState(state, reply)) => {
+                    let result = Self::lock(proxy).set_state(state);
+                    reply.send(result).ok();
+                }
+                Ok(MainThreadMessage::Close(reply)) => break Some(reply),
+                // everything else is sent by the plugin, which lives in the plugin process
+                Ok(_) => {}
+                Err(_) => break None,
+            }
+        };
+
+        {
+            let mut proxy = Self::lock(proxy);
+
+            if !proxy.crashed {
+                proxy.request(&Request::Close).ok();
+            }
+
+            proxy.kill();
+        }
+
+        drop(alive);
+
+        if let Some(close_reply) = close_reply {
+            close_reply.send(Ok(())).ok();
+        }
+    }
+
+    fn lock(proxy: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        proxy.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response, PluginError> {
+        if !self.crashed && write_message(&mut self.stdin, "", request).is_ok() {
+            while let Ok(response) = self.responses.recv_timeout(self.timeout) {
+                match response {
+                    Response::Notification(notification) => self.notify(notification),
+                    response => return Ok(response),
+                }
+            }
+        }
+
+        // the plugin process either exited, or stopped responding
+        self.kill();
+        self.crashed = true;
+
+        Err(PluginError::Crashed(self.last_state.clone()))
+    }
+
+    fn notify(&self, notification: Notification) {
+        let notification = match notification {
+            Notification::Restarted(reasons) => PluginNotification::Restarted(reasons),
+            Notification::RestartFailed(err) => {
+                PluginNotification::RestartFailed(HostError::PluginProcess(err))
+            }
+        };
+
+        self.notifier.send(notification).ok();
+    }
+
+    fn kill(&mut self) {
+        // the plugin process exits by itself once it's closed, in which case this fails harmlessly
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+
+    fn process(
+        &mut self,
+        input_audio: &[Vec<f32>],
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        frames: usize,
+        input_events: &EventBuffer,
+    ) -> Result<(Vec<Vec<f32>>, EventBuffer), PluginError> {
+        let output_channels = outputs.iter().sum::<usize>();
+        let events_offset = (input_audio.len() + output_channels) * frames;
+        let events = encode_events(input_events);
+
+        self.audio
+            .write(0, input_audio)
+            .map_err(shared_memory_error)?;
+        self.audio
+            .write_bytes(events_offset, &events)
+            .map_err(shared_memory_error)?;
+
+        match self.request(&Request::Process {
+            inputs,
+            outputs,
+            frames,
+            events: events.len(),
+        })? {
+            Response::Processed(Ok(output_events)) => Ok((
+                self.audio
+                    .read(input_audio.len() * frames, output_channels, frames)
+                    .map_err(shared_memory_error)?,
+                decode_events(
+                    &self
+                        .audio
+                        .read_bytes(events_offset, output_events)
+                        .map_err(shared_memory_error)?,
+                ),
+            )),
+            Response::Processed(Err(err)) => Err(PluginError::PluginProcess(err)),
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+
+    fn reconfigure(&mut self, config: PluginAudioConfiguration) -> Result<(), PluginError> {
+        match self.request(&Request::Reconfigure(config.into()))? {
+            Response::Reconfigured(result) => result.map_err(PluginError::PluginProcess),
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+
+    #[cfg(feature = "audio-ports")]
+    fn get_audio_ports(&mut self) -> Result<AudioPortLayout, PluginError> {
+        match self.request(&Request::GetAudioPorts)? {
+            Response::AudioPorts(ports) => Ok(ports),
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+
+    fn get_counter(&mut self) -> Result<u64, PluginError> {
+        match self.request(&Request::GetCounter)? {
+            Response::Counter(result) => result.map_err(PluginError::PluginProcess),
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+
+    #[cfg(feature = "state")]
+    fn get_state(&mut self) -> Result<Vec<u8>, PluginError> {
+        match self.request(&Request::GetState)? {
+            Response::State(result) => {
+                let len = result.map_err(PluginError::PluginProcess)?;
+                let state = self.audio.read_bytes(0, len).map_err(shared_memory_error)?;
+                self.last_state = Some(state.clone());
+                Ok(state)
+            }
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+
+    #[cfg(feature = "state")]
+    fn set_state(&mut self, state: Vec<u8>) -> Result<(), PluginError> {
+        self.audio
+            .write_bytes(0, &state)
+            .map_err(shared_memory_error)?;
+
+        match self.request(&Request::SetState(state.len()))? {
+            Response::StateLoaded(result) => {
+                result.map_err(PluginError::PluginProcess)?;
+                self.last_state = Some(state);
+                Ok(())
+            }
+            _ => Err(PluginError::PluginProcess(unexpected_response())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_without_inputs_are_as_long_as_the_outputs() {
+        let mut left = vec![1.0f32; 64];
+        let mut right = vec![1.0f32; 64];
+        let mut output_port = [left.as_mut_slice(), right.as_mut_slice()];
+        let output_audio = [output_port.as_mut_slice()];
+
+        assert_eq!(block_frames::<f32>(&[], &output_audio), 64);
+        assert!(input_channels::<f32>(&[], 64).is_empty());
+    }
+
+    #[test]
+    fn input_channels_are_cut_to_the_block() {
+        let long = vec![0.5; 128];
+        let short = vec![0.25; 64];
+        let input_audio: [&[&[f64]]; 2] = [&[long.as_slice()], &[short.as_slice()]];
+
+        assert_eq!(
+            input_channels(&input_audio, 64),
+            vec![vec![0.5; 64], vec![0.25; 64]]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+#[cfg(feature = "audio-ports")]
+use crate::AudioPortLayout;
+use crate::{HostConfig, PluginSelector, RestartReason};
+use clack_host::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// plugins are free to print to stdout themselves, so responses are marked to be told apart from their output
+pub const RESPONSE_MARKER: &str = "generic-clap-host-response:";
+
+/// the size of `clap_event_header`, which every event starts with
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// the event space of the events defined by CLAP itself
+const CORE_EVENT_SPACE_ID: u16 = 0;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct AudioConfig {
+    sample_rate: f64,
+    min_frames_count: u32,
+    max_frames_count: u32,
+}
+
+impl From<PluginAudioConfiguration> for AudioConfig {
+    fn from(config: PluginAudioConfiguration) -> Self {
+        Self {
+            sample_rate: config.sample_rate,
+            min_frames_count: config.min_frames_count,
+            max_frames_count: config.max_frames_count,
+        }
+    }
+}
+
+impl From<AudioConfig> for PluginAudioConfiguration {
+    fn from(config: AudioConfig) -> Self {
+        Self {
+            sample_rate: config.sample_rate,
+            min_frames_count: config.min_frames_count,
+            max_frames_count: config.max_frames_count,
+        }
+    }
+}
+
+/// Sent from the host to the plugin process, one per line.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Start {
+        plugin: PluginSelector,
+        host_config: HostConfig,
+        config: AudioConfig,
+    },
+    /// the input audio is in shared memory, as the channels of every input port one after the other, with `frames`
+    /// samples each, followed by room for the output audio and then `events` bytes of input events, see
+    /// [`encode_events`]
+    ///
+    /// `inputs` and `outputs` hold how many channels each input and output port has.
+    Process {
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+        frames: usize,
+        events: usize,
+    },
+    Reconfigure(AudioConfig),
+    #[cfg(feature = "audio-ports")]
+    GetAudioPorts,
+    GetCounter,
+    #[cfg(feature = "state")]
+    GetState,
+    /// the state is in shared memory, with this many bytes
+    #[cfg(feature = "state")]
+    SetState(usize),
+    Close,
+}
+
+/// Sent from the plugin process to the host, one per line, prefixed with [`RESPONSE_MARKER`].
+///
+/// Every request is answered by exactly one response, optionally preceded by notifications.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Started(Result<(), String>),
+    /// the output audio is in shared memory right after the input audio, laid out like the input audio, and is
+    /// followed by this many bytes of output events in place of the input events
+    Processed(Result<usize, String>),
+    Reconfigured(Result<(), String>),
+    #[cfg(feature = "audio-ports")]
+    AudioPorts(AudioPortLayout),
+    Counter(Result<u64, String>),
+    /// the state is in shared memory, with this many bytes
+    #[cfg(feature = "state")]
+    State(Result<usize, String>),
+    #[cfg(feature = "state")]
+    StateLoaded(Result<(), String>),
+    Notification(Notification),
+    Closed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Notification {
+    Restarted(Vec<RestartReason>),
+    RestartFailed(String),
+}
+
+pub fn write_message(
+    writer: &mut impl Write,
+    marker: &str,
+    message: &impl Serialize,
+) -> io::Result<()> {
+    writeln!(writer, "{marker}{}", serde_json::to_string(message)?)?;
+    writer.flush()
+}
+
+/// Writes the events that can be sent to another process one after the other, see [`decode_events`].
+pub fn encode_events(events: &EventBuffer) -> Vec<u8> {
+    events
+        .iter()
+        .map(UnknownEvent::as_bytes)
+        .filter(|bytes| is_sendable(bytes))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Reads the events [`encode_events`] wrote.
+///
+/// The bytes come from another process, so only events that pass [`split_events`] are decoded.
+pub fn decode_events(bytes: &[u8]) -> EventBuffer {
+    let events = split_events(bytes);
+    let mut buffer = EventBuffer::with_capacity(events.len());
+
+    for bytes in events {
+        // events may contain 8 byte fields, and a byte slice isn't guaranteed to be aligned for them
+        let mut aligned: Vec<_> = bytes
+            .chunks(8)
+            .map(|chunk| {
+                let mut word = [0; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u64::from_ne_bytes(word)
+            })
+            .collect();
+
+        // the cookie of param value and param mod events points into the process that created them
+        if matches!(header(bytes).2, 5 | 6) {
+            aligned[3] = 0;
+        }
+
+        // SAFETY: the bytes are aligned, and are exactly as large as the core event type their header names
+        let event = unsafe {
+            UnknownEvent::from_bytes_unchecked(std::slice::from_raw_parts(
+                aligned.as_ptr().cast::<u8>(),
+                bytes.len(),
+            ))
+        };
+
+        buffer.push(event);
+    }
+
+    buffer
+}
+
+/// Splits `bytes` into events, keeping only those [`is_sendable`] accepts.
+///
+/// Splitting stops at the first header whose size is smaller than a header or larger than the bytes that are left,
+/// since nothing after it can be told apart.
+fn split_events(mut bytes: &[u8]) -> Vec<&[u8]> {
+    let mut events = Vec::new();
+
+    while bytes.len() >= EVENT_HEADER_SIZE {
+        let size = header(bytes).0 as usize;
+        if !(EVENT_HEADER_SIZE..=bytes.len()).contains(&size) {
+            break;
+        }
+
+        let (event, rest) = bytes.split_at(size);
+        bytes = rest;
+
+        if is_sendable(event) {
+            events.push(event);
+        }
+    }
+
+    events
+}
+
+/// Whether `event` is of a core event type that can be sent to another process, and exactly as large as that type.
+///
+/// `SysEx` events aren't, since they point to their data in the process that created them.
+fn is_sendable(event: &[u8]) -> bool {
+    if event.len() < EVENT_HEADER_SIZE {
+        return false;
+    }
+
+    let (size, space_id, event_type) = header(event);
+
+    let expected = match event_type {
+        // note on, note off, note choke, note end and note expression
+        0..=4 => 40,
+        // param value and param mod
+        5 | 6 => 56,
+        // param gesture begin and param gesture end
+        7 | 8 => 20,
+        // transport
+        9 => 104,
+        // MIDI
+        10 => 24,
+        // MIDI 2
+        12 => 36,
+        _ => return false,
+    };
+
+    space_id == CORE_EVENT_SPACE_ID && size as usize == expected && event.len() == expected
+}
+
+/// The size, space id and type of the header `event` starts with, which has to be at least as long as a header.
+fn header(event: &[u8]) -> (u32, u16, u16) {
+    (
+        u32::from_ne_bytes(event[0..4].try_into().unwrap()),
+        u16::from_ne_bytes(event[8..10].try_into().unwrap()),
+        u16::from_ne_bytes(event[10..12].try_into().unwrap()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(size: u32, space_id: u16, event_type: u16, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        bytes[0..4].copy_from_slice(&size.to_ne_bytes());
+        bytes[8..10].copy_from_slice(&space_id.to_ne_bytes());
+        bytes[10..12].copy_from_slice(&event_type.to_ne_bytes());
+        bytes
+    }
+
+    #[test]
+    fn core_events_are_split() {
+        let events = [
+            event(40, 0, 0, 40),
+            event(20, 0, 7, 20),
+            event(104, 0, 9, 104),
+            event(36, 0, 12, 36),
+        ];
+
+        assert_eq!(split_events(&events.concat()), events);
+    }
+
+    #[test]
+    fn unsendable_events_are_skipped() {
+        let midi = event(24, 0, 10, 24);
+        let bytes = [
+            // SysEx
+            event(40, 0, 11, 40),
+            // unknown type
+            event(24, 0, 13, 24),
+            // not a core event
+            event(24, 1, 10, 24),
+            // the wrong size for a note event
+            event(48, 0, 0, 48),
+            midi.clone(),
+        ]
+        .concat();
+
+        assert_eq!(split_events(&bytes), [midi]);
+    }
+
+    #[test]
+    fn malformed_sizes_stop_splitting() {
+        let midi = event(24, 0, 10, 24);
+
+        let undersized = [midi.clone(), event(8, 0, 10, 24), midi.clone()].concat();
+        assert_eq!(split_events(&undersized), [midi.as_slice()]);
+
+        let oversized = [midi.clone(), event(64, 0, 10, 24)].concat();
+        assert_eq!(split_events(&oversized), [midi.as_slice()]);
+
+        let truncated = [midi.clone(), midi[..12].to_vec()].concat();
+        assert_eq!(split_events(&truncated), [midi]);
+
+        assert!(split_events(&[]).is_empty());
+    }
+}
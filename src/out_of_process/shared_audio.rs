@@ -0,0 +1,164 @@
+use memmap2::MmapMut;
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering::SeqCst},
+};
+
+const SAMPLE_SIZE: usize = size_of::<f32>();
+
+/// Audio buffers shared between the host and a plugin process, backed by a memory mapped file.
+///
+/// Channels are stored one after the other, and may be followed by other data, like events. The two processes take
+/// turns accessing the buffers, synchronized by the requests and responses sent over the control pipe.
+pub struct SharedAudio {
+    path: PathBuf,
+    file: File,
+    map: Option<MmapMut>,
+    /// the host created the file, so it also removes it again
+    owned: bool,
+}
+
+impl SharedAudio {
+    pub fn create() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "generic-clap-host-{}-{}.audio",
+            process::id(),
+            COUNTER.fetch_add(1, SeqCst)
+        ));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(Self {
+            path,
+            file,
+            map: None,
+            owned: true,
+        })
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            map: None,
+            owned: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `channels`, starting `offset` samples in.
+    pub fn write(&mut self, offset: usize, channels: &[Vec<f32>]) -> io::Result<()> {
+        let samples = channels.iter().map(Vec::len).sum::<usize>();
+        let bytes = self.map(offset, samples * SAMPLE_SIZE, true)?;
+
+        for (sample, bytes) in channels
+            .iter()
+            .flatten()
+            .zip(bytes.chunks_exact_mut(SAMPLE_SIZE))
+        {
+            bytes.copy_from_slice(&sample.to_ne_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Reads `channels` channels of `frames` samples each, starting `offset` samples in.
+    pub fn read(
+        &mut self,
+        offset: usize,
+        channels: usize,
+        frames: usize,
+    ) -> io::Result<Vec<Vec<f32>>> {
+        if frames == 0 {
+            return Ok(vec![Vec::new(); channels]);
+        }
+
+        let bytes = self.map(offset, channels * frames * SAMPLE_SIZE, false)?;
+
+        Ok(bytes
+            .chunks_exact(frames * SAMPLE_SIZE)
+            .map(|channel| {
+                channel
+                    .chunks_exact(SAMPLE_SIZE)
+                    .map(|sample| f32::from_ne_bytes(sample.try_into().unwrap()))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Writes `bytes`, starting `offset` samples in, e.g. right after the audio.
+    pub fn write_bytes(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        self.map(offset, bytes.len(), true)?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Reads `len` bytes, starting `offset` samples in.
+    pub fn read_bytes(&mut self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        Ok(self.map(offset, len, false)?.to_vec())
+    }
+
+    /// `len` bytes, starting `offset` samples in.
+    ///
+    /// The file is grown if it's too small and `grow` is set. Otherwise, the other process sent a length the file
+    /// doesn't have room for, which is an error.
+    fn map(&mut self, offset: usize, len: usize, grow: bool) -> io::Result<&mut [u8]> {
+        let start = offset.checked_mul(SAMPLE_SIZE);
+        let Some((start, end)) = start.and_then(|start| Some((start, start.checked_add(len)?)))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the shared memory can't be that large",
+            ));
+        };
+
+        // empty files can't be mapped
+        if len == 0 {
+            return Ok(&mut []);
+        }
+
+        if self.map.as_ref().is_none_or(|map| map.len() < end) {
+            if self.file.metadata()?.len() < end as u64 {
+                if !grow {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the shared memory is smaller than the data in it",
+                    ));
+                }
+
+                self.file.set_len(end as u64)?;
+            }
+
+            // SAFETY: the file is only accessed by the host and the plugin process, which never do so at the same time
+            self.map = Some(unsafe { MmapMut::map_mut(&self.file)? });
+        }
+
+        Ok(self
+            .map
+            .as_mut()
+            .map_or(&mut [], |map| &mut map[start..end]))
+    }
+}
+
+impl Drop for SharedAudio {
+    fn drop(&mut self) {
+        if self.owned {
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
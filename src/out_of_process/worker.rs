@@ -0,0 +1,206 @@
+use super::{
+    protocol::{
+        decode_events, encode_events, write_message, Notification, Request, Response,
+        RESPONSE_MARKER,
+    },
+    shared_audio::SharedAudio,
+    AUDIO_ENV, BUNDLE_ENV,
+};
+use crate::{run, scanner::load_bundle, ClapPlugin, PluginNotification};
+use clack_host::prelude::*;
+use std::{env, io, mem, path::Path, process};
+
+/// Turns the current process into a plugin worker if it was spawned by
+/// [`run_out_of_process`](crate::run_out_of_process).
+///
+/// Call this at the very start of `main`. In a plugin worker it hosts the requested plugin until the host closes it,
+/// and then exits; otherwise it returns immediately.
+pub fn run_plugin_worker() {
+    let (Some(bundle_path), Some(audio_path)) = (env::var_os(BUNDLE_ENV), env::var_os(AUDIO_ENV))
+    else {
+        return;
+    };
+
+    let served = serve(
+        Path::new(&bundle_path),
+        Path::new(&audio_path),
+        io::stdin().lines(),
+    );
+
+    process::exit(i32::from(served.is_err()));
+}
+
+fn respond(response: &Response) -> io::Result<()> {
+    // the plugin may print to stdout from its own threads, so the lock is only held while writing
+    write_message(&mut io::stdout().lock(), RESPONSE_MARKER, response)
+}
+
+fn serve(
+    bundle_path: &Path,
+    audio_path: &Path,
+    mut requests: impl Iterator<Item = io::Result<String>>,
+) -> io::Result<()> {
+    let mut audio = SharedAudio::open(audio_path)?;
+
+    let Some(Request::Start {
+        plugin,
+        host_config,
+        config,
+    }) = requests
+        .next()
+        .transpose()?
+        .map(|line| serde_json::from_str(&line))
+        .transpose()?
+    else {
+        return Ok(());
+    };
+
+    let started = load_bundle(bundle_path)
+        .map_err(|err| err.to_string())
+        .and_then(|bundle| {
+            run(bundle, &plugin, &host_config, config.into()).map_err(|err| err.to_string())
+        });
+
+    let mut plugin = match started {
+        Ok(plugin) => {
+            respond(&Response::Started(Ok(())))?;
+            plugin
+        }
+        Err(err) => return respond(&Response::Started(Err(err))),
+    };
+
+    // dropped before the plugin, so that the plugin's own handle is the last one, which closes it
+    let control = plugin.control().clone();
+
+    let mut output_events = EventBuffer::new();
+
+    // the host closing our stdin means it's gone, in which case dropping the plugin closes it
+    for line in requests {
+        let response = match serde_json::from_str(&line?)? {
+            Request::Start { .. } => continue,
+            Request::Process {
+                inputs,
+                outputs,
+                frames,
+                events,
+            } => process(
+                &mut plugin,
+                &mut audio,
+                &mut output_events,
+                &inputs,
+                &outputs,
+                frames,
+                events,
+            )?,
+            Request::Reconfigure(config) => {
+                // the next block is only sent once the plugin is reconfigured
+                plugin.audio_mut().release();
+
+                Response::Reconfigured(
+                    control
+                        .reconfigure(config.into())
+                        .map_err(|err| err.to_string()),
+                )
+            }
+            #[cfg(feature = "audio-ports")]
+            Request::GetAudioPorts => {
+                Response::AudioPorts(control.audio_ports().unwrap_or_default())
+            }
+            Request::GetCounter => {
+                Response::Counter(plugin.audio().get_counter().map_err(|err| err.to_string()))
+            }
+            #[cfg(feature = "state")]
+            Request::GetState => match control.get_state() {
+                Ok(state) => {
+                    audio.write_bytes(0, &state)?;
+                    Response::State(Ok(state.len()))
+                }
+                Err(err) => Response::State(Err(err.to_string())),
+            },
+            #[cfg(feature = "state")]
+            Request::SetState(len) => {
+                let state = audio.read_bytes(0, len)?;
+
+                Response::StateLoaded(control.set_state(state).map_err(|err| err.to_string()))
+            }
+            Request::Close => {
+                plugin.close().ok();
+                return respond(&Response::Closed);
+            }
+        };
+
+        for notification in control.notifications() {
+            respond(&Response::Notification(match notification {
+                PluginNotification::Restarted(reasons) => Notification::Restarted(reasons),
+                PluginNotification::RestartFailed(err) => {
+                    Notification::RestartFailed(err.to_string())
+                }
+            }))?;
+        }
+
+        respond(&response)?;
+    }
+
+    Ok(())
+}
+
+/// Processes the block described by a [`Request::Process`], and writes the output back into shared memory.
+fn process(
+    plugin: &mut ClapPlugin,
+    audio: &mut SharedAudio,
+    output_events: &mut EventBuffer,
+    inputs: &[usize],
+    outputs: &[usize],
+    frames: usize,
+    events: usize,
+) -> io::Result<Response> {
+    let input_channels = inputs.iter().sum::<usize>();
+    let output_channels = outputs.iter().sum::<usize>();
+    let events_offset = (input_channels + output_channels) * frames;
+
+    let input = audio.read(0, input_channels, frames)?;
+    let input_events = decode_events(&audio.read_bytes(events_offset, events)?);
+    let mut output = vec![vec![0.0; frames]; output_channels];
+
+    let input_slices: Vec<_> = input.iter().map(Vec::as_slice).collect();
+    let mut output_slices: Vec<_> = output.iter_mut().map(Vec::as_mut_slice).collect();
+
+    match plugin.audio_mut().process_audio(
+        &ports(&input_slices, inputs),
+        &mut ports_mut(&mut output_slices, outputs),
+        &input_events,
+        output_events,
+    ) {
+        Ok(()) => {
+            let encoded = encode_events(output_events);
+            audio.write(input_channels * frames, &output)?;
+            audio.write_bytes(events_offset, &encoded)?;
+            Ok(Response::Processed(Ok(encoded.len())))
+        }
+        Err(err) => Ok(Response::Processed(Err(err.to_string()))),
+    }
+}
+
+/// Groups `channels` into ports with `counts` channels each.
+fn ports<'a, T>(mut channels: &'a [T], counts: &[usize]) -> Vec<&'a [T]> {
+    counts
+        .iter()
+        .map(|&count| {
+            let (port, rest) = channels.split_at(count.min(channels.len()));
+            channels = rest;
+            port
+        })
+        .collect()
+}
+
+/// Like [`ports`], for mutable channels.
+fn ports_mut<'a, T>(mut channels: &'a mut [T], counts: &[usize]) -> Vec<&'a mut [T]> {
+    counts
+        .iter()
+        .map(|&count| {
+            let (port, rest) = mem::take(&mut channels).split_at_mut(count.min(channels.len()));
+            channels = rest;
+            port
+        })
+        .collect()
+}
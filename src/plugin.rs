@@ -0,0 +1,399 @@
+#[cfg(feature = "out-of-process")]
+use crate::out_of_process::RemoteAudio;
+#[cfg(feature = "audio-ports")]
+use crate::AudioPortLayout;
+use crate::{
+    audio_buffers::Sample, audio_processor::AudioThreadProcessor, main_thread::MainThreadSender,
+    MainThreadMessage, PluginError, PluginNotification,
+};
+use clack_host::prelude::*;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    time::Duration,
+};
+
+/// how long closing a plugin waits for the main thread before giving up on it
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running plugin, made up of an [`AudioHandle`] for processing audio and a [`ControlHandle`] for everything else.
+#[derive(Debug)]
+pub struct ClapPlugin {
+    audio: AudioHandle,
+    control: ControlHandle,
+}
+
+impl ClapPlugin {
+    pub(crate) const fn new(audio: AudioHandle, control: ControlHandle) -> Self {
+        Self { audio, control }
+    }
+
+    #[must_use]
+    pub const fn audio(&self) -> &AudioHandle {
+        &self.audio
+    }
+
+    #[must_use]
+    pub const fn control(&self) -> &ControlHandle {
+        &self.control
+    }
+
+    #[must_use]
+    pub fn audio_mut(&mut self) -> &mut AudioHandle {
+        &mut self.audio
+    }
+
+    /// Splits the plugin into its handles, e.g. to move the audio handle to the audio thread.
+    ///
+    /// The plugin keeps running until it's closed, or every clone of the control handle is dropped.
+    #[must_use]
+    pub fn split(self) -> (AudioHandle, ControlHandle) {
+        (self.audio, self.control)
+    }
+
+    /// See [`ControlHandle::is_alive`].
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        self.control.is_alive()
+    }
+
+    /// See [`ControlHandle::close`].
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or didn't finish closing in time
+    pub fn close(self) -> Result<(), PluginError> {
+        // the audio handle hands the audio processor over as it's dropped, which closing waits for
+        drop(self.audio);
+
+        self.control.close()
+    }
+}
+
+/// Processes audio through a plugin, on the caller's thread.
+///
+/// The plugin's audio processor lives in here, so processing starts, stops and runs on whichever thread owns the
+/// handle, as CLAP expects of the audio thread. Processing neither allocates nor blocks, so it's safe to do from a
+/// real-time audio callback. Plugins hosted out of process are the exception, since every block is sent to the plugin
+/// process and waited for.
+///
+/// To reactivate or close the plugin, its main thread asks for the audio processor, which is handed over with the next
+/// block, or right away if the handle was [released](Self::release). Blocks are silent until the main thread hands it
+/// back. Dropping the handle hands it over for good.
+///
+/// Out of process, the audio and control handles share a single connection to the plugin process, which handles one
+/// request at a time. A block sent while a [`ControlHandle`] request is being handled waits for it to finish, so e.g. a
+/// plugin that's slow to save its state delays the next block by as long, and a request that takes longer than the
+/// sandbox's timeout gets the plugin process killed.
+pub struct AudioHandle {
+    backend: AudioBackend,
+    alive: Weak<()>,
+}
+
+enum AudioBackend {
+    Local(AudioThreadProcessor),
+    #[cfg(feature = "out-of-process")]
+    Remote(RemoteAudio),
+}
+
+impl Debug for AudioHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioHandle").finish_non_exhaustive()
+    }
+}
+
+impl AudioHandle {
+    pub(crate) fn local(processor: AudioThreadProcessor, alive: &Arc<()>) -> Self {
+        Self {
+            backend: AudioBackend::Local(processor),
+            alive: Arc::downgrade(alive),
+        }
+    }
+
+    #[cfg(feature = "out-of-process")]
+    pub(crate) fn remote(remote: RemoteAudio, alive: &Arc<()>) -> Self {
+        Self {
+            backend: AudioBackend::Remote(remote),
+            alive: Arc::downgrade(alive),
+        }
+    }
+
+    /// Processes one block of audio, writing the plugin's output into `output_audio` and `output_events`.
+    ///
+    /// The audio is passed per port, in the order of the plugin's audio ports, and then per channel. With the
+    /// `audio-ports` feature, the plugin always gets buffers for all of its ports and channels, so e.g. an instrument
+    /// can be processed without any input buffers, and only the outputs the caller is interested in. The block is as
+    /// long as the shortest of the buffers. Nothing is allocated, unless the block is longer than the plugin was
+    /// activated for, has more ports or channels than any block before it, or the plugin outputs more events than fit
+    /// into `output_events`.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or if the plugin fails to process the audio, and with
+    /// [`PluginError::NotProcessing`] if the plugin refuses to start processing, in which case the output is silent
+    pub fn process_audio(
+        &mut self,
+        input_audio: &[&[&[f32]]],
+        output_audio: &mut [&mut [&mut [f32]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        self.process(input_audio, output_audio, input_events, output_events)
+    }
+
+    /// Like [`process_audio`](Self::process_audio), but with 64-bit audio.
+    ///
+    /// Ports that support 64-bit buffers are handed the audio as it is, the audio of all other ports is converted.
+    /// Plugins hosted out of process always process 32-bit audio.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or if the plugin fails to process the audio, and with
+    /// [`PluginError::NotProcessing`] if the plugin refuses to start processing, in which case the output is silent
+    pub fn process_audio_f64(
+        &mut self,
+        input_audio: &[&[&[f64]]],
+        output_audio: &mut [&mut [&mut [f64]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        self.process(input_audio, output_audio, input_events, output_events)
+    }
+
+    fn process<T: Sample>(
+        &mut self,
+        input_audio: &[&[&[T]]],
+        output_audio: &mut [&mut [&mut [T]]],
+        input_events: &EventBuffer,
+        output_events: &mut EventBuffer,
+    ) -> Result<(), PluginError> {
+        if self.alive.strong_count() == 0 {
+            return Err(PluginError::HostThreadGone);
+        }
+
+        match &mut self.backend {
+            AudioBackend::Local(processor) => {
+                processor.process(input_audio, output_audio, input_events, output_events)
+            }
+            #[cfg(feature = "out-of-process")]
+            AudioBackend::Remote(remote) => {
+                remote.process(input_audio, output_audio, input_events, output_events)
+            }
+        }
+    }
+
+    /// Hands buffers set up by the caller straight to the plugin, and returns the status the plugin returned.
+    ///
+    /// Unlike [`process_audio`](Self::process_audio), nothing is copied and the plugin is never put to sleep, so it's
+    /// up to the caller to stop calling this once the plugin returns [`ProcessStatus::Sleep`].
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, isn't processing right now, or fails to process the audio, or if
+    /// it's hosted out of process
+    pub fn process_raw(
+        &mut self,
+        input_audio: &InputAudioBuffers<'_>,
+        output_audio: &mut OutputAudioBuffers<'_>,
+        input_events: &InputEvents<'_>,
+        output_events: &mut OutputEvents<'_>,
+    ) -> Result<ProcessStatus, PluginError> {
+        if self.alive.strong_count() == 0 {
+            return Err(PluginError::HostThreadGone);
+        }
+
+        match &mut self.backend {
+            AudioBackend::Local(processor) => {
+                processor.process_raw(input_audio, output_audio, input_events, output_events)
+            }
+            #[cfg(feature = "out-of-process")]
+            AudioBackend::Remote(_) => Err(PluginError::PluginProcess(
+                "raw buffers can't be sent to the plugin process".to_owned(),
+            )),
+        }
+    }
+
+    /// Stops processing until the next block, and meanwhile lets the plugin's main thread reactivate or close the
+    /// plugin without waiting for a block.
+    ///
+    /// Call this before the thread that processes audio stops processing blocks for a while, e.g. before the audio
+    /// device is stopped, or before that thread waits for [`ControlHandle::reconfigure`]. Plugins hosted out of process
+    /// are released by the plugin process itself.
+    pub fn release(&mut self) {
+        match &mut self.backend {
+            AudioBackend::Local(processor) => processor.release(),
+            #[cfg(feature = "out-of-process")]
+            AudioBackend::Remote(_) => {}
+        }
+    }
+
+    /// How many frames the plugin has processed so far.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore
+    pub fn get_counter(&self) -> Result<u64, PluginError> {
+        if self.alive.strong_count() == 0 {
+            return Err(PluginError::HostThreadGone);
+        }
+
+        match &self.backend {
+            AudioBackend::Local(processor) => Ok(processor.steady_time()),
+            #[cfg(feature = "out-of-process")]
+            AudioBackend::Remote(remote) => remote.get_counter(),
+        }
+    }
+}
+
+/// Controls a plugin's configuration, state and lifetime from any thread.
+///
+/// Requests are handled on the plugin's main thread, one at a time. The plugin is closed by [`Self::close`], or once
+/// every clone of the handle is dropped.
+#[derive(Clone, Debug)]
+pub struct ControlHandle {
+    control: Arc<Control>,
+}
+
+#[derive(Debug)]
+struct Control {
+    sender: MainThreadSender,
+    notifications: Mutex<Receiver<PluginNotification>>,
+    alive: Weak<()>,
+}
+
+impl ControlHandle {
+    pub(crate) fn new(
+        sender: MainThreadSender,
+        notifications: Receiver<PluginNotification>,
+        alive: &Arc<()>,
+    ) -> Self {
+        Self {
+            control: Arc::new(Control {
+                sender,
+                notifications: Mutex::new(notifications),
+                alive: Arc::downgrade(alive),
+            }),
+        }
+    }
+
+    /// Whether the plugin is still running.
+    ///
+    /// Once it isn't, because the plugin was closed, it panicked, or its runtime shut down, every other method errors
+    /// with [`PluginError::HostThreadGone`]. Closing the plugin's GUI doesn't close the plugin. Plugins hosted out of
+    /// process keep running after the plugin process crashed, and error with [`PluginError::Crashed`] instead.
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        self.control.alive.strong_count() > 0
+    }
+
+    /// Stops processing, deactivates the plugin, closes its GUI, destroys it and unloads its bundle.
+    ///
+    /// Closing waits for the [`AudioHandle`] to hand the audio processor over with its next block, or to be released or
+    /// dropped. If that doesn't happen within half a second, this errors, and the plugin is closed once it does. If the
+    /// plugin doesn't finish closing within a few seconds, it's left running in the background. Dropping the last
+    /// clone of the handle does the same.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or didn't finish closing in time, with
+    /// [`PluginError::HandOverTimeout`] if the audio processor wasn't handed over
+    pub fn close(&self) -> Result<(), PluginError> {
+        self.control.close()
+    }
+
+    fn request<T>(
+        &self,
+        message: impl FnOnce(Sender<Result<T, PluginError>>) -> MainThreadMessage,
+    ) -> Result<T, PluginError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        self.control
+            .sender
+            .send(message(sender))
+            .map_err(|_| PluginError::HostThreadGone)?;
+
+        // the reply channel is dropped without a reply if the plugin is closed in the meantime
+        receiver.recv().unwrap_or(Err(PluginError::HostThreadGone))
+    }
+
+    /// Deactivates the plugin and activates it again with `config`, e.g. after the sample rate or buffer size changed.
+    ///
+    /// The plugin instance, and with it its state and GUI, is kept alive throughout. The plugin is reactivated once the
+    /// [`AudioHandle`] hands the audio processor over with its next block, or right away if it's released, see
+    /// [`AudioHandle::release`]. If neither happens within half a second, the plugin is left as it is.
+    ///
+    /// # Errors
+    ///
+    /// errors with [`PluginError::HandOverTimeout`] if the audio processor wasn't handed over in time, and otherwise if
+    /// the plugin isn't running anymore, or if the plugin fails to activate with the new configuration, in which case
+    /// it outputs silence until it's successfully reconfigured
+    pub fn reconfigure(&self, config: PluginAudioConfiguration) -> Result<(), PluginError> {
+        self.request(|reply| MainThreadMessage::Reconfigure(config, reply))
+    }
+
+    /// Notifications the plugin sent since the last call, oldest first.
+    ///
+    /// Every notification is only returned once, even if the handle has been cloned.
+    #[must_use]
+    pub fn notifications(&self) -> Vec<PluginNotification> {
+        self.control
+            .notifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_iter()
+            .collect()
+    }
+
+    /// The plugin's current audio ports, which [`AudioHandle::process_audio`] expects buffers for.
+    ///
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore
+    #[cfg(feature = "audio-ports")]
+    pub fn audio_ports(&self) -> Result<AudioPortLayout, PluginError> {
+        self.request(MainThreadMessage::GetAudioPorts)
+    }
+
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or if the plugin doesn't support or fails to save its state
+    #[cfg(feature = "state")]
+    pub fn get_state(&self) -> Result<Vec<u8>, PluginError> {
+        self.request(MainThreadMessage::GetState)
+    }
+
+    /// # Errors
+    ///
+    /// errors if the plugin isn't running anymore, or if the plugin doesn't support or fails to load its state
+    #[cfg(feature = "state")]
+    pub fn set_state(&self, state: Vec<u8>) -> Result<(), PluginError> {
+        self.request(|reply| MainThreadMessage::SetState(state, reply))
+    }
+}
+
+impl Control {
+    fn close(&self) -> Result<(), PluginError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        self.sender
+            .send(MainThreadMessage::Close(sender))
+            .map_err(|_| PluginError::HostThreadGone)?;
+
+        match receiver.recv_timeout(CLOSE_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Disconnected) => Err(PluginError::HostThreadGone),
+            Err(RecvTimeoutError::Timeout) => Err(PluginError::Unresponsive),
+        }
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        if self.alive.strong_count() > 0 {
+            self.close().ok();
+        }
+    }
+}
@@ -0,0 +1,377 @@
+#[cfg(feature = "audio-ports")]
+use crate::extensions::audio_ports::AudioPortLayout;
+#[cfg(feature = "gui")]
+use crate::extensions::gui::GuiExt;
+#[cfg(feature = "timer")]
+use crate::extensions::timer::Timers;
+use crate::{
+    audio_processor::{AudioThreadProcessor, MainThreadProcessor},
+    main_thread::{
+        expire_deferred, handle_deferred, handle_message, Deferred, MainThreadSender,
+        HAND_OVER_TIMEOUT,
+    },
+    AudioProcessor, Host, HostError, MainThread, MainThreadMessage, PluginError,
+    PluginNotification, Shared,
+};
+#[cfg(feature = "gui")]
+use clack_extensions::gui::GuiSize;
+#[cfg(feature = "timer")]
+use clack_extensions::timer::PluginTimer;
+use clack_host::{prelude::*, process::StoppedPluginAudioProcessor};
+#[cfg(feature = "timer")]
+use std::rc::Rc;
+use std::{
+    ffi::{CStr, CString},
+    mem,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+#[cfg(feature = "gui")]
+use winit::{
+    dpi::Size,
+    event::WindowEvent,
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowId},
+};
+
+/// how often the main thread checks whether the audio thread handed the audio processor over, while it waits for it
+const HAND_OVER_POLL: Duration = Duration::from_millis(1);
+
+/// Everything the runtime needs to start a plugin instance.
+pub struct Start {
+    pub bundle: PluginBundle,
+    pub plugin_id: CString,
+    pub host_info: HostInfo,
+    pub config: PluginAudioConfiguration,
+    /// the sending half of `receiver`, handed to the plugin so it can send messages to its own main thread
+    pub main_thread_sender: MainThreadSender,
+    pub receiver: Receiver<MainThreadMessage>,
+    pub notifier: Sender<PluginNotification>,
+    pub alive: Arc<()>,
+}
+
+/// A plugin instance living on the runtime's main thread, together with its GUI.
+pub struct HostedPlugin {
+    /// declared before `bundle`, since the instance has to be destroyed before its bundle is unloaded
+    instance: PluginInstance<Host>,
+    audio_processor: MainThreadProcessor,
+    receiver: Receiver<MainThreadMessage>,
+    /// requests that wait for the audio thread to hand the audio processor over
+    deferred: Vec<Deferred>,
+    /// set once the plugin's owner asked for it to be closed, which also waits for the audio processor
+    closing: bool,
+    /// replied to once the plugin is closed, or with [`PluginError::HandOverTimeout`] by the deadline
+    close_reply: Option<(Sender<Result<(), PluginError>>, Instant)>,
+    #[cfg(feature = "timer")]
+    timers: Option<(Rc<Timers>, PluginTimer)>,
+    #[cfg(feature = "gui")]
+    gui: Option<GuiExt>,
+    /// only set for embedded GUIs, floating GUIs manage their own window
+    #[cfg(feature = "gui")]
+    window: Option<Window>,
+    /// [`ClapPlugin::is_alive`](crate::ClapPlugin::is_alive) checks whether this is still around
+    _alive: Arc<()>,
+    bundle: PluginBundle,
+}
+
+impl HostedPlugin {
+    /// Starts the plugin, handing back the audio processor for the plugin's audio thread.
+    pub fn start(
+        start: Start,
+        #[cfg(feature = "gui")] target: &ActiveEventLoop,
+    ) -> Result<(Self, AudioThreadProcessor), HostError> {
+        #[cfg_attr(not(any(feature = "audio-ports", feature = "gui")), expect(unused_mut))]
+        let (mut instance, audio_processor) = instantiate(
+            &start.bundle,
+            &start.plugin_id,
+            &start.host_info,
+            start.config,
+            start.main_thread_sender,
+            start.notifier,
+        )?;
+
+        #[cfg_attr(not(feature = "audio-ports"), expect(unused_mut))]
+        let mut audio_processor = AudioProcessor::new(audio_processor, start.config);
+
+        #[cfg(feature = "audio-ports")]
+        audio_processor.set_ports(&AudioPortLayout::query(&mut instance));
+
+        let (audio_processor, audio_thread_processor) = audio_processor.split();
+
+        #[cfg(feature = "timer")]
+        let timers =
+            instance.access_handler(|h| h.timer_support.map(|ext| (h.timers.clone(), ext)));
+
+        // plugins without a GUI, or without one we can embed or float, still get a main thread
+        #[cfg(feature = "gui")]
+        let mut gui = instance
+            .access_handler(|h| h.gui)
+            .map(|gui| GuiExt::new(gui, &mut instance.plugin_handle()))
+            .filter(|gui| gui.needs_floating().is_some());
+
+        #[cfg(feature = "gui")]
+        let window = match &mut gui {
+            Some(gui) if gui.needs_floating() == Some(true) => {
+                gui.open_floating(&mut instance.plugin_handle());
+                None
+            }
+            Some(gui) => Some(gui.open_embedded(&mut instance.plugin_handle(), target)),
+            None => None,
+        };
+
+        let hosted_plugin = Self {
+            instance,
+            audio_processor,
+            receiver: start.receiver,
+            deferred: Vec::new(),
+            closing: false,
+            close_reply: None,
+            #[cfg(feature = "timer")]
+            timers,
+            #[cfg(feature = "gui")]
+            gui,
+            #[cfg(feature = "gui")]
+            window,
+            _alive: start.alive,
+            bundle: start.bundle,
+        };
+
+        Ok((hosted_plugin, audio_thread_processor))
+    }
+
+    /// Ticks the plugin's timers and handles the messages sent to its main thread.
+    ///
+    /// Returns `false` once the plugin should be closed.
+    pub fn poll(&mut self) -> bool {
+        #[cfg(feature = "timer")]
+        if let Some((timers, timer_ext)) = &self.timers {
+            timers.tick_timers(timer_ext, &mut self.instance.plugin_handle());
+        }
+
+        loop {
+            let Ok(message) = self.receiver.try_recv() else {
+                break;
+            };
+
+            match message {
+                MainThreadMessage::Close(reply) => {
+                    self.closing = true;
+                    self.close_reply = Some((reply, Instant::now() + HAND_OVER_TIMEOUT));
+                }
+                #[cfg(feature = "gui")]
+                MainThreadMessage::GuiClosed => self.close_gui(),
+                #[cfg(feature = "gui")]
+                MainThreadMessage::GuiRequestResized(new_size) => self.resize_gui(new_size),
+                message => handle_message(
+                    &mut self.instance,
+                    message,
+                    &mut self.audio_processor,
+                    &mut self.deferred,
+                ),
+            }
+        }
+
+        if self.deferred.is_empty() && !self.closing {
+            return true;
+        }
+
+        let Some(audio_processor) = self.audio_processor.get() else {
+            self.audio_processor.request_hand_over();
+            self.expire();
+            return true;
+        };
+
+        handle_deferred(&mut self.instance, audio_processor, &mut self.deferred);
+
+        if self.closing {
+            return false;
+        }
+
+        self.audio_processor.hand_back();
+
+        true
+    }
+
+    /// Fails the requests that waited for the audio processor past their deadline, so that nobody waits for the audio
+    /// thread indefinitely. A plugin that's closing is still closed once the audio processor is handed over.
+    fn expire(&mut self) {
+        expire_deferred(&mut self.deferred);
+
+        if self
+            .close_reply
+            .as_ref()
+            .is_some_and(|(_, deadline)| *deadline <= Instant::now())
+        {
+            if let Some((reply, _)) = self.close_reply.take() {
+                reply.send(Err(PluginError::HandOverTimeout)).ok();
+            }
+        }
+    }
+
+    /// Asks the audio thread to hand the audio processor over, and returns whether it has, so that the plugin can be
+    /// closed.
+    pub fn prepare_close(&mut self) -> bool {
+        if self.audio_processor.get().is_some() {
+            return true;
+        }
+
+        self.audio_processor.request_hand_over();
+        false
+    }
+
+    /// When the plugin next needs to be polled, for its timers to fire on time, or to check whether the audio thread
+    /// handed the audio processor over while that's waited for.
+    pub fn next_tick(&self) -> Option<Instant> {
+        let hand_over =
+            (!self.deferred.is_empty() || self.closing).then(|| Instant::now() + HAND_OVER_POLL);
+
+        #[cfg(feature = "timer")]
+        let timers = self
+            .timers
+            .as_ref()
+            .and_then(|(timers, _)| timers.next_tick());
+        #[cfg(not(feature = "timer"))]
+        let timers = None;
+
+        hand_over.into_iter().chain(timers).min()
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn window_id(&self) -> Option<WindowId> {
+        self.window.as_ref().map(Window::id)
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.close_gui(),
+            &WindowEvent::Resized(size) => {
+                let (Some(gui), Some(window)) = (&self.gui, &self.window) else {
+                    return;
+                };
+
+                let actual_size = gui.resize(
+                    &mut self.instance.plugin_handle(),
+                    Size::Physical(size),
+                    window.scale_factor(),
+                );
+
+                if actual_size != size.into() {
+                    let _ = window.request_inner_size(actual_size);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Closes the plugin's GUI, while keeping the plugin itself running.
+    #[cfg(feature = "gui")]
+    fn close_gui(&mut self) {
+        if let Some(gui) = &mut self.gui {
+            gui.destroy(&mut self.instance.plugin_handle());
+        }
+
+        self.window = None;
+    }
+
+    #[cfg(feature = "gui")]
+    fn resize_gui(&mut self, new_size: GuiSize) {
+        let Some(gui) = &self.gui else {
+            return;
+        };
+
+        if let Some(window) = &self.window {
+            let _ = window.request_inner_size(gui.gui_size_to_winit_size(new_size));
+        } else {
+            gui.resize(
+                &mut self.instance.plugin_handle(),
+                gui.gui_size_to_winit_size(new_size),
+                1.0f64,
+            );
+        }
+    }
+
+    /// Drops the host's side of the instance without calling into the plugin again, leaking the plugin and its bundle.
+    ///
+    /// Used after the plugin panicked, when it may be in an inconsistent state.
+    pub fn abandon(self) {
+        let Self {
+            instance,
+            audio_processor,
+            #[cfg(feature = "gui")]
+            gui,
+            #[cfg(feature = "gui")]
+            window,
+            bundle,
+            ..
+        } = self;
+
+        mem::forget((instance, audio_processor, bundle));
+        #[cfg(feature = "gui")]
+        mem::forget((gui, window));
+    }
+
+    /// Deactivates the plugin, closes its GUI, destroys it and unloads its bundle.
+    ///
+    /// The audio thread stops processing before it hands the audio processor over. If it hasn't, the plugin may still
+    /// be processing, so it's abandoned instead.
+    pub fn close(mut self) {
+        let Some(audio_processor) = self.audio_processor.get() else {
+            return self.abandon();
+        };
+
+        audio_processor.deactivate(&mut self.instance);
+
+        #[cfg(feature = "gui")]
+        self.close_gui();
+
+        let Self {
+            instance,
+            bundle,
+            close_reply,
+            ..
+        } = self;
+
+        drop(instance);
+        drop(bundle);
+
+        if let Some((close_reply, _)) = close_reply {
+            close_reply.send(Ok(())).ok();
+        }
+    }
+}
+
+fn instantiate(
+    bundle: &PluginBundle,
+    plugin_id: &CStr,
+    host_info: &HostInfo,
+    config: PluginAudioConfiguration,
+    sender: MainThreadSender,
+    notifier: Sender<PluginNotification>,
+) -> Result<(PluginInstance<Host>, StoppedPluginAudioProcessor<Host>), HostError> {
+    let mut instance = PluginInstance::<Host>::new(
+        |()| Shared::new(sender, notifier),
+        |shared| {
+            #[cfg(not(feature = "state"))]
+            let _ = shared;
+
+            MainThread::new(
+                #[cfg(feature = "state")]
+                shared,
+            )
+        },
+        bundle,
+        plugin_id,
+        host_info,
+    )
+    .map_err(HostError::Instantiation)?;
+
+    let audio_processor = instance
+        .activate(|_, _| {}, config)
+        .map_err(HostError::Activation)?;
+
+    Ok((instance, audio_processor))
+}
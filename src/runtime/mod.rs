@@ -0,0 +1,411 @@
+use crate::{
+    audio_processor::AudioThreadProcessor, main_thread::MainThreadSender, AudioHandle, ClapPlugin,
+    ControlHandle, HostConfig, HostError, PluginSelector,
+};
+use clack_host::prelude::*;
+use instance::{HostedPlugin, Start};
+#[cfg(not(feature = "gui"))]
+use std::sync::mpsc::RecvTimeoutError;
+#[cfg(feature = "gui")]
+use std::sync::mpsc::TryRecvError;
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{Receiver, SendError, Sender},
+        Arc, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "gui")]
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
+    window::WindowId,
+};
+
+mod instance;
+
+/// how long shutting down waits for the audio threads to hand over the audio processors of the plugins still running
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// how long shutting down sleeps before checking again whether the audio processors were handed over
+const SHUTDOWN_SLEEP: Duration = Duration::from_millis(1);
+
+enum RuntimeMessage {
+    Start(Box<Start>, Sender<Result<AudioThreadProcessor, HostError>>),
+    /// one of the plugins has messages waiting, see [`Waker`]
+    Wake,
+    Shutdown,
+}
+
+/// Sends messages to the runtime's main thread, and wakes it up to handle them.
+///
+/// The main thread waits until it's woken up, or until one of its plugins needs to be polled.
+#[derive(Clone, Debug)]
+pub struct Waker {
+    sender: Sender<RuntimeMessage>,
+    /// wakes the event loop up, which doesn't wait on `sender`
+    #[cfg(feature = "gui")]
+    proxy: Option<EventLoopProxy<()>>,
+}
+
+impl Waker {
+    fn send(&self, message: RuntimeMessage) -> Result<(), SendError<RuntimeMessage>> {
+        self.sender.send(message)?;
+
+        #[cfg(feature = "gui")]
+        if let Some(proxy) = &self.proxy {
+            // the event loop is gone once the runtime shut down
+            proxy.send_event(()).ok();
+        }
+
+        Ok(())
+    }
+
+    /// Wakes the main thread up to poll its plugins for messages.
+    pub fn wake(&self) {
+        self.send(RuntimeMessage::Wake).ok();
+    }
+}
+
+/// A single main thread shared by any number of plugin instances.
+///
+/// With the `gui` feature, the main thread also runs the process' only winit event loop, which every plugin's window
+/// lives on.
+#[derive(Debug)]
+pub struct HostRuntime {
+    waker: Waker,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Default for HostRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostRuntime {
+    /// Spawns a new main thread.
+    ///
+    /// With the `gui` feature, only one runtime can exist per process, so prefer [`Self::global`].
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        #[cfg(not(feature = "gui"))]
+        let thread = std::thread::spawn(move || run_main_thread(&receiver));
+
+        #[cfg(feature = "gui")]
+        let (thread, proxy) = {
+            let (sender_proxy, receiver_proxy) = std::sync::mpsc::channel();
+            let thread = std::thread::spawn(move || run_main_thread(&receiver, &sender_proxy));
+
+            // if the event loop can't be created, the main thread is gone, and so is `sender_proxy`
+            (thread, receiver_proxy.recv().ok())
+        };
+
+        Self {
+            waker: Waker {
+                sender,
+                #[cfg(feature = "gui")]
+                proxy,
+            },
+            thread: Some(thread),
+        }
+    }
+
+    /// The runtime used by [`run`](crate::run), which is started the first time it's needed.
+    pub fn global() -> &'static Self {
+        static GLOBAL: OnceLock<HostRuntime> = OnceLock::new();
+
+        GLOBAL.get_or_init(Self::new)
+    }
+
+    /// Instantiates the plugin chosen by `plugin` from `bundle` on this runtime's main thread and starts processing
+    /// audio.
+    ///
+    /// The plugin is told it's being hosted by the host described in `host_config`.
+    ///
+    /// # Errors
+    ///
+    /// errors if the bundle doesn't expose a plugin factory, doesn't contain the requested plugin, if `host_config`
+    /// isn't valid, if the plugin fails to instantiate or activate, panics while starting, or if the runtime's main
+    /// thread is gone
+    pub fn run(
+        &self,
+        bundle: PluginBundle,
+        plugin: &PluginSelector,
+        host_config: &HostConfig,
+        config: PluginAudioConfiguration,
+    ) -> Result<ClapPlugin, HostError> {
+        let plugin_id = plugin.resolve(&bundle)?;
+        let host_info = host_config.host_info()?;
+
+        let (sender_plugin, receiver_plugin) = std::sync::mpsc::channel();
+        let (sender_started, receiver_started) = std::sync::mpsc::channel();
+        let (sender_notifications, receiver_notifications) = std::sync::mpsc::channel();
+
+        let alive = Arc::new(());
+
+        let start = Start {
+            bundle,
+            plugin_id,
+            host_info,
+            config,
+            main_thread_sender: MainThreadSender::new(
+                sender_plugin.clone(),
+                Some(self.waker.clone()),
+            ),
+            receiver: receiver_plugin,
+            notifier: sender_notifications,
+            alive: alive.clone(),
+        };
+
+        self.waker
+            .send(RuntimeMessage::Start(Box::new(start), sender_started))
+            .map_err(|_| HostError::RuntimeGone)?;
+
+        let audio_processor = receiver_started
+            .recv()
+            .unwrap_or(Err(HostError::RuntimeGone))?;
+
+        Ok(ClapPlugin::new(
+            AudioHandle::local(audio_processor, &alive),
+            ControlHandle::new(
+                MainThreadSender::new(sender_plugin, Some(self.waker.clone())),
+                receiver_notifications,
+                &alive,
+            ),
+        ))
+    }
+}
+
+impl Drop for HostRuntime {
+    /// Closes every plugin still running on this runtime and waits for the main thread to finish.
+    fn drop(&mut self) {
+        self.waker.send(RuntimeMessage::Shutdown).ok();
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Instances {
+    instances: Vec<HostedPlugin>,
+}
+
+impl Instances {
+    /// Handles the messages that were sent to the runtime since the last call.
+    ///
+    /// Returns `false` once the runtime should shut down.
+    #[cfg(feature = "gui")]
+    fn handle_messages(
+        &mut self,
+        receiver: &Receiver<RuntimeMessage>,
+        target: &ActiveEventLoop,
+    ) -> bool {
+        loop {
+            match receiver.try_recv() {
+                Ok(message) => {
+                    if !self.handle_message(message, target) {
+                        return false;
+                    }
+                }
+                Err(TryRecvError::Disconnected) => return false,
+                Err(TryRecvError::Empty) => return true,
+            }
+        }
+    }
+
+    /// Starts the plugin `message` asks for, if any.
+    ///
+    /// Returns `false` once the runtime should shut down.
+    fn handle_message(
+        &mut self,
+        message: RuntimeMessage,
+        #[cfg(feature = "gui")] target: &ActiveEventLoop,
+    ) -> bool {
+        match message {
+            RuntimeMessage::Start(start, reply) => {
+                let started = catch_unwind(AssertUnwindSafe(|| {
+                    HostedPlugin::start(
+                        *start,
+                        #[cfg(feature = "gui")]
+                        target,
+                    )
+                }))
+                .unwrap_or(Err(HostError::StartPanicked));
+
+                match started {
+                    Ok((instance, audio_processor)) => {
+                        self.instances.push(instance);
+                        reply.send(Ok(audio_processor)).ok();
+                    }
+                    Err(err) => {
+                        reply.send(Err(err)).ok();
+                    }
+                }
+
+                true
+            }
+            // the plugins are polled after every message
+            RuntimeMessage::Wake => true,
+            RuntimeMessage::Shutdown => false,
+        }
+    }
+
+    fn poll(&mut self) {
+        let mut index = 0;
+
+        while index < self.instances.len() {
+            if self.guard(index, HostedPlugin::poll) {
+                index += 1;
+            }
+        }
+    }
+
+    /// Calls `f` on the instance at `index`, and removes the instance if `f` returns `false` or panics.
+    ///
+    /// A panicking plugin is abandoned without calling into it again, so that it can't take down the other plugins on
+    /// the runtime. Returns whether the instance is still there.
+    fn guard(&mut self, index: usize, f: impl FnOnce(&mut HostedPlugin) -> bool) -> bool {
+        match catch_unwind(AssertUnwindSafe(|| f(&mut self.instances[index]))) {
+            Ok(true) => true,
+            Ok(false) => {
+                let instance = self.instances.swap_remove(index);
+                catch_unwind(AssertUnwindSafe(|| instance.close())).ok();
+                false
+            }
+            Err(_) => {
+                self.instances.swap_remove(index).abandon();
+                false
+            }
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    fn window_event(&mut self, window_id: WindowId, event: &WindowEvent) {
+        if let Some(index) = self
+            .instances
+            .iter()
+            .position(|instance| instance.window_id() == Some(window_id))
+        {
+            self.guard(index, |instance| {
+                instance.window_event(event);
+                true
+            });
+        }
+    }
+
+    /// When the plugins next need to be polled, or `None` if only messages need handling.
+    fn next_tick(&self) -> Option<Instant> {
+        self.instances
+            .iter()
+            .filter_map(HostedPlugin::next_tick)
+            .min()
+    }
+
+    /// Closes every plugin, once its audio thread handed its audio processor over.
+    ///
+    /// Plugins whose audio processor isn't handed over in time are abandoned.
+    fn close(&mut self) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        loop {
+            let mut prepared = true;
+            for instance in &mut self.instances {
+                prepared &= instance.prepare_close();
+            }
+
+            if prepared || Instant::now() >= deadline {
+                break;
+            }
+
+            std::thread::sleep(SHUTDOWN_SLEEP);
+        }
+
+        for instance in self.instances.drain(..) {
+            catch_unwind(AssertUnwindSafe(|| instance.close())).ok();
+        }
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_main_thread(receiver: &Receiver<RuntimeMessage>) {
+    let mut instances = Instances::default();
+
+    loop {
+        let message = instances.next_tick().map_or_else(
+            || receiver.recv().map_err(RecvTimeoutError::from),
+            |next_tick| receiver.recv_timeout(next_tick.saturating_duration_since(Instant::now())),
+        );
+
+        match message {
+            Ok(message) => {
+                if !instances.handle_message(message) {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        instances.poll();
+    }
+
+    instances.close();
+}
+
+/// Sends the runtime an [`EventLoopProxy`] to wake the event loop up with once it's running.
+#[cfg(feature = "gui")]
+fn run_main_thread(receiver: &Receiver<RuntimeMessage>, proxy: &Sender<EventLoopProxy<()>>) {
+    let mut builder = EventLoop::builder();
+
+    // plugins can only embed their GUI into X11 windows on Linux
+    #[cfg(target_os = "linux")]
+    winit::platform::x11::EventLoopBuilderExtX11::with_x11(&mut builder);
+
+    // the runtime's main thread isn't the process' main thread
+    #[cfg(target_os = "linux")]
+    winit::platform::x11::EventLoopBuilderExtX11::with_any_thread(&mut builder, true);
+    #[cfg(target_os = "windows")]
+    winit::platform::windows::EventLoopBuilderExtWindows::with_any_thread(&mut builder, true);
+
+    let event_loop = builder.build().unwrap();
+
+    proxy.send(event_loop.create_proxy()).ok();
+
+    let mut instances = Instances::default();
+
+    #[expect(deprecated)]
+    event_loop
+        .run(move |event, target| {
+            match event {
+                Event::WindowEvent { window_id, event } => {
+                    instances.window_event(window_id, &event);
+                }
+                Event::LoopExiting => {
+                    instances.close();
+                    return;
+                }
+                _ => {}
+            }
+
+            if !instances.handle_messages(receiver, target) {
+                target.exit();
+                return;
+            }
+
+            instances.poll();
+
+            target.set_control_flow(
+                instances
+                    .next_tick()
+                    .map_or(ControlFlow::Wait, ControlFlow::WaitUntil),
+            );
+        })
+        .unwrap();
+}
@@ -0,0 +1,72 @@
+use super::{PluginScanner, ScanEvent, ScanReport};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// A scan running on a background thread, see [`PluginScanner::scan_in_background`].
+///
+/// Dropping the handle cancels the scan.
+#[derive(Debug)]
+pub struct ScanHandle {
+    events: Receiver<ScanEvent>,
+    cancel: Arc<AtomicBool>,
+    thread: Option<JoinHandle<ScanReport>>,
+}
+
+impl ScanHandle {
+    pub(crate) fn spawn(scanner: PluginScanner) -> Self {
+        let (sender, events) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_clone = cancel.clone();
+
+        let thread = thread::spawn(move || {
+            scanner.scan_with(&cancel_clone, |event| {
+                // the receiver may have stopped listening, the scan result is still returned by `join`
+                sender.send(event).ok();
+            })
+        });
+
+        Self {
+            events,
+            cancel,
+            thread: Some(thread),
+        }
+    }
+
+    /// Progress and results, streamed as each bundle is scanned.
+    #[must_use]
+    pub fn events(&self) -> &Receiver<ScanEvent> {
+        &self.events
+    }
+
+    /// Stops the scan after the bundle that is currently being scanned.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Waits for the scan to finish, returning everything that was scanned until then.
+    ///
+    /// # Panics
+    ///
+    /// panics if the scanning thread panicked
+    #[must_use]
+    pub fn join(mut self) -> ScanReport {
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+impl Drop for ScanHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
@@ -0,0 +1,349 @@
+use super::ScanOutcome;
+use etcetera::{choose_base_strategy, BaseStrategy as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    time::SystemTime,
+};
+
+/// bump this whenever the layout of the cache file changes, so that stale caches are discarded
+const CACHE_VERSION: u32 = 2;
+
+/// tells apart the temporary files of the cache writes within this process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Remembers the outcome of scanning each bundle, so that unchanged bundles don't have to be loaded again.
+///
+/// Entries are keyed by bundle path and invalidated whenever the bundle's size or modification time changes.
+///
+/// Several scans may use the cache file at once, e.g. a scan and a watcher, see [`Self::save`].
+#[derive(Debug, Default)]
+pub struct ScanCache {
+    bundles: HashMap<PathBuf, CachedBundle>,
+    /// the entries inserted or removed since the cache was loaded, `None` for the removed ones
+    changes: HashMap<PathBuf, Option<CachedBundle>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedBundle {
+    path: PathBuf,
+    stamp: BundleStamp,
+    outcome: ScanOutcome,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleStamp {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl BundleStamp {
+    pub fn new(metadata: &Metadata) -> Option<Self> {
+        Some(Self {
+            size: metadata.len(),
+            modified: metadata.modified().ok()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    bundles: Vec<CachedBundle>,
+}
+
+impl ScanCache {
+    /// The location the cache is stored at, inside the user's cache directory.
+    ///
+    /// # Panics
+    ///
+    /// panics if the user's home directory can't be determined
+    #[must_use]
+    pub fn default_path() -> PathBuf {
+        choose_base_strategy()
+            .unwrap()
+            .cache_dir()
+            .join("generic-clap-host")
+            .join("plugins.json")
+    }
+
+    /// Loads the cache from [`Self::default_path`].
+    ///
+    /// A missing, unreadable or outdated cache file results in an empty cache.
+    #[must_use]
+    pub fn load() -> Self {
+        Self::load_from(&Self::default_path())
+    }
+
+    #[must_use]
+    pub fn load_from(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok())
+            .filter(|file| file.version == CACHE_VERSION)
+            .map(|file| Self {
+                bundles: file
+                    .bundles
+                    .into_iter()
+                    .map(|bundle| (bundle.path.clone(), bundle))
+                    .collect(),
+                changes: HashMap::new(),
+            })
+            .unwrap_or_default()
+    }
+
+    /// Writes the cache to [`Self::default_path`].
+    ///
+    /// Only the entries this cache inserted or removed since it was loaded are written, so that the entries other scans
+    /// saved in the meantime are kept.
+    ///
+    /// # Errors
+    ///
+    /// errors if the cache directory can't be created or the cache file can't be written
+    pub fn save(&self) -> io::Result<()> {
+        self.save_to(&Self::default_path())
+    }
+
+    /// Like [`Self::save`], but writes to the cache file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// errors if `path`'s parent directory can't be created or the cache file can't be written
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut bundles = Self::load_from(path).bundles;
+        for (path, bundle) in &self.changes {
+            match bundle {
+                Some(bundle) => bundles.insert(path.clone(), bundle.clone()),
+                None => bundles.remove(path),
+            };
+        }
+
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            bundles: bundles.into_values().collect(),
+        };
+
+        // write to a temporary file first, so that a crash mid-write can't leave a truncated cache behind, and name it
+        // uniquely, so that concurrent scans don't write to the same one
+        let tmp = path.with_extension(format!(
+            "json.{}-{}.tmp",
+            process::id(),
+            TMP_COUNTER.fetch_add(1, Relaxed)
+        ));
+
+        let written =
+            fs::write(&tmp, serde_json::to_vec(&file)?).and_then(|()| fs::rename(&tmp, path));
+        if written.is_err() {
+            fs::remove_file(tmp).ok();
+        }
+
+        written
+    }
+
+    /// Bundles that didn't yield any plugins, together with the reason.
+    ///
+    /// These aren't retried until they change on disk, or until [`Self::forget_failures`] is called.
+    pub fn failures(&self) -> impl Iterator<Item = (&Path, &ScanOutcome)> {
+        self.bundles
+            .values()
+            .filter(|bundle| !bundle.outcome.is_loaded())
+            .map(|bundle| (bundle.path.as_path(), &bundle.outcome))
+    }
+
+    /// Forgets all failed bundles, so that they are retried on the next scan.
+    pub fn forget_failures(&mut self) {
+        self.retain_bundles(|bundle| bundle.outcome.is_loaded());
+    }
+
+    pub fn clear(&mut self) {
+        self.retain_bundles(|_| false);
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.retain_bundles(|bundle| keep(&bundle.path));
+    }
+
+    fn retain_bundles(&mut self, mut keep: impl FnMut(&CachedBundle) -> bool) {
+        self.bundles.retain(|path, bundle| {
+            let kept = keep(bundle);
+            if !kept {
+                self.changes.insert(path.clone(), None);
+            }
+            kept
+        });
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.bundles.remove(path);
+        self.changes.insert(path.to_owned(), None);
+    }
+
+    pub(crate) fn get(&self, path: &Path, stamp: BundleStamp) -> Option<&ScanOutcome> {
+        self.bundles
+            .get(path)
+            .filter(|bundle| bundle.stamp == stamp)
+            .map(|bundle| &bundle.outcome)
+    }
+
+    pub(crate) fn insert(&mut self, path: PathBuf, stamp: BundleStamp, outcome: ScanOutcome) {
+        if outcome.is_cacheable() {
+            let bundle = CachedBundle {
+                path: path.clone(),
+                stamp,
+                outcome,
+            };
+
+            self.changes.insert(path.clone(), Some(bundle.clone()));
+            self.bundles.insert(path, bundle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("generic-clap-host-test-{}", process::id()))
+            .join(name)
+    }
+
+    fn stamp(size: u64) -> BundleStamp {
+        BundleStamp {
+            size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(size),
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = cache_path("save_and_load.json");
+
+        let mut cache = ScanCache::default();
+        cache.insert("a.clap".into(), stamp(1), ScanOutcome::Loaded(Vec::new()));
+        cache.insert("b.clap".into(), stamp(2), ScanOutcome::MissingFactory);
+        cache.save_to(&path).unwrap();
+
+        let loaded = ScanCache::load_from(&path);
+        assert_eq!(
+            loaded.get(Path::new("a.clap"), stamp(1)),
+            Some(&ScanOutcome::Loaded(Vec::new()))
+        );
+        assert_eq!(
+            loaded.get(Path::new("b.clap"), stamp(2)),
+            Some(&ScanOutcome::MissingFactory)
+        );
+        assert_eq!(loaded.failures().count(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn changed_bundles_are_missed() {
+        let mut cache = ScanCache::default();
+        cache.insert("a.clap".into(), stamp(1), ScanOutcome::NoDescriptors);
+
+        assert!(cache.get(Path::new("a.clap"), stamp(1)).is_some());
+        assert!(cache.get(Path::new("a.clap"), stamp(2)).is_none());
+        assert!(cache.get(Path::new("b.clap"), stamp(1)).is_none());
+    }
+
+    #[test]
+    fn missing_or_outdated_files_load_empty() {
+        assert!(ScanCache::load_from(&cache_path("missing.json"))
+            .bundles
+            .is_empty());
+
+        let path = cache_path("outdated.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            serde_json::to_vec(&CacheFile {
+                version: CACHE_VERSION - 1,
+                bundles: vec![CachedBundle {
+                    path: "a.clap".into(),
+                    stamp: stamp(1),
+                    outcome: ScanOutcome::MissingFactory,
+                }],
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert!(ScanCache::load_from(&path).bundles.is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn forget_failures() {
+        let mut cache = ScanCache::default();
+        cache.insert("a.clap".into(), stamp(1), ScanOutcome::Loaded(Vec::new()));
+        cache.insert("b.clap".into(), stamp(2), ScanOutcome::MissingFactory);
+        cache.forget_failures();
+
+        assert!(cache.get(Path::new("a.clap"), stamp(1)).is_some());
+        assert!(cache.get(Path::new("b.clap"), stamp(2)).is_none());
+    }
+
+    #[test]
+    fn saving_keeps_what_others_saved_in_the_meantime() {
+        let path = cache_path("concurrent.json");
+
+        let mut initial = ScanCache::default();
+        initial.insert("a.clap".into(), stamp(1), ScanOutcome::MissingFactory);
+        initial.insert("b.clap".into(), stamp(2), ScanOutcome::MissingFactory);
+        initial.save_to(&path).unwrap();
+
+        let mut scan = ScanCache::load_from(&path);
+        let mut watcher = ScanCache::load_from(&path);
+
+        scan.insert("c.clap".into(), stamp(3), ScanOutcome::NoDescriptors);
+        scan.retain(|path| path != Path::new("a.clap"));
+        scan.save_to(&path).unwrap();
+
+        watcher.insert("b.clap".into(), stamp(4), ScanOutcome::NoDescriptors);
+        watcher.save_to(&path).unwrap();
+
+        let loaded = ScanCache::load_from(&path);
+        assert!(loaded.get(Path::new("a.clap"), stamp(1)).is_none());
+        assert_eq!(
+            loaded.get(Path::new("b.clap"), stamp(4)),
+            Some(&ScanOutcome::NoDescriptors)
+        );
+        assert_eq!(
+            loaded.get(Path::new("c.clap"), stamp(3)),
+            Some(&ScanOutcome::NoDescriptors)
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saving_leaves_no_temporary_files_behind() {
+        let path = cache_path("temporary").join("plugins.json");
+
+        ScanCache::default().save_to(&path).unwrap();
+        ScanCache::default().save_to(&path).unwrap();
+
+        let files = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["plugins.json"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
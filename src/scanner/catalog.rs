@@ -0,0 +1,353 @@
+use super::PluginFeature;
+use clack_host::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+    slice, vec,
+};
+
+/// Everything the host needs to know about a plugin without keeping its bundle loaded.
+// loading the bundle is unsafe because of what's in the bundle, not because of anything about these fields
+#[expect(clippy::unsafe_derive_deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PluginInfo {
+    pub id: String,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub description: String,
+    pub features: Vec<PluginFeature>,
+    pub bundle_path: PathBuf,
+    /// index of this plugin's descriptor in the bundle's plugin factory
+    pub index: u32,
+}
+
+impl PluginInfo {
+    /// Loads the bundle this plugin lives in.
+    ///
+    /// # Errors
+    ///
+    /// errors if the bundle can no longer be loaded from `bundle_path`
+    ///
+    /// # Safety
+    ///
+    /// Loading a bundle runs its initialization code, so the bundle at `bundle_path` has to be trusted, see
+    /// [`PluginBundle::load`]. Scanning only made sure it exposed this plugin back then, the file may have been
+    /// replaced since.
+    pub unsafe fn load_bundle(&self) -> Result<PluginBundle, PluginBundleError> {
+        // SAFETY: the caller trusts the bundle
+        unsafe { PluginBundle::load(&self.bundle_path) }
+    }
+
+    #[must_use]
+    pub fn has_feature(&self, feature: &PluginFeature) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Whether the plugin has every one of `features`.
+    #[must_use]
+    pub fn has_features(&self, features: &[PluginFeature]) -> bool {
+        features.iter().all(|feature| self.has_feature(feature))
+    }
+
+    #[must_use]
+    pub fn is_instrument(&self) -> bool {
+        self.has_feature(&PluginFeature::Instrument)
+    }
+
+    #[must_use]
+    pub fn is_audio_effect(&self) -> bool {
+        self.has_feature(&PluginFeature::AudioEffect)
+    }
+
+    #[must_use]
+    pub fn is_note_effect(&self) -> bool {
+        self.has_feature(&PluginFeature::NoteEffect)
+    }
+
+    #[must_use]
+    pub fn is_analyzer(&self) -> bool {
+        self.has_feature(&PluginFeature::Analyzer)
+    }
+}
+
+/// A plugin that was found, but isn't part of the catalog because another copy with the same id takes precedence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowedPlugin {
+    pub plugin: PluginInfo,
+    /// the bundle containing the copy that is used instead
+    pub shadowed_by: PathBuf,
+}
+
+/// All plugins found while scanning, one entry per plugin id.
+///
+/// When the same plugin id is found more than once, copies installed in the user's home directory take precedence over
+/// system-wide ones, then the highest version wins, then the copy found first. The other copies are kept in
+/// [`Self::shadowed`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginCatalog {
+    plugins: Vec<PluginInfo>,
+    shadowed: Vec<ShadowedPlugin>,
+}
+
+impl PluginCatalog {
+    /// Builds a catalog with one entry per plugin id out of every plugin that was found, keeping the other copies in
+    /// [`Self::shadowed`].
+    ///
+    /// See [`PluginCatalog`] for which copy is kept.
+    #[must_use]
+    pub fn deduplicated(plugins: impl IntoIterator<Item = PluginInfo>) -> Self {
+        let home = etcetera::home_dir().ok();
+        let is_user_path = |path: &Path| home.as_ref().is_some_and(|home| path.starts_with(home));
+
+        let mut groups: Vec<Vec<PluginInfo>> = Vec::new();
+        let mut group_of_id = HashMap::<String, usize>::new();
+
+        for plugin in plugins {
+            match group_of_id.entry(plugin.id.clone()) {
+                Entry::Occupied(entry) => groups[*entry.get()].push(plugin),
+                Entry::Vacant(entry) => {
+                    entry.insert(groups.len());
+                    groups.push(vec![plugin]);
+                }
+            }
+        }
+
+        let mut plugins = Vec::with_capacity(groups.len());
+        let mut shadowed = Vec::new();
+
+        for mut group in groups {
+            // the sort is stable, so ties keep the order they were found in
+            group.sort_by(|a, b| {
+                is_user_path(&b.bundle_path)
+                    .cmp(&is_user_path(&a.bundle_path))
+                    .then_with(|| compare_versions(&b.version, &a.version))
+            });
+
+            let mut group = group.into_iter();
+            let Some(winner) = group.next() else {
+                continue;
+            };
+
+            shadowed.extend(group.map(|plugin| ShadowedPlugin {
+                plugin,
+                shadowed_by: winner.bundle_path.clone(),
+            }));
+            plugins.push(winner);
+        }
+
+        Self { plugins, shadowed }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, PluginInfo> {
+        self.plugins.iter()
+    }
+
+    /// Looks up a plugin by its CLAP id.
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<&PluginInfo> {
+        self.plugins.iter().find(|plugin| plugin.id == id)
+    }
+
+    /// Copies of plugins that were found, but are hidden by another copy with the same id.
+    #[must_use]
+    pub fn shadowed(&self) -> &[ShadowedPlugin] {
+        &self.shadowed
+    }
+
+    /// All plugins that have every one of `features`, e.g. `[AudioEffect, Stereo]` for stereo effects.
+    pub fn with_features<'a>(
+        &'a self,
+        features: &'a [PluginFeature],
+    ) -> impl Iterator<Item = &'a PluginInfo> {
+        self.plugins
+            .iter()
+            .filter(move |plugin| plugin.has_features(features))
+    }
+
+    pub fn instruments(&self) -> impl Iterator<Item = &PluginInfo> {
+        self.plugins.iter().filter(|plugin| plugin.is_instrument())
+    }
+
+    pub fn audio_effects(&self) -> impl Iterator<Item = &PluginInfo> {
+        self.plugins
+            .iter()
+            .filter(|plugin| plugin.is_audio_effect())
+    }
+
+    pub fn note_effects(&self) -> impl Iterator<Item = &PluginInfo> {
+        self.plugins.iter().filter(|plugin| plugin.is_note_effect())
+    }
+
+    pub fn analyzers(&self) -> impl Iterator<Item = &PluginInfo> {
+        self.plugins.iter().filter(|plugin| plugin.is_analyzer())
+    }
+}
+
+/// compares dotted version strings segment by segment, numerically where both segments are numbers
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn segments(version: &str) -> Vec<&str> {
+        version
+            .split(['.', '-', '+', ' '])
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+
+    let (a, b) = (segments(a), segments(b));
+
+    for (a, b) in a.iter().zip(&b) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Err(_), Err(_)) => a.cmp(b),
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+impl IntoIterator for PluginCatalog {
+    type Item = PluginInfo;
+    type IntoIter = vec::IntoIter<PluginInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.plugins.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PluginCatalog {
+    type Item = &'a PluginInfo;
+    type IntoIter = slice::Iter<'a, PluginInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.plugins.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(id: &str, version: &str, bundle_path: &str) -> PluginInfo {
+        PluginInfo {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            vendor: String::new(),
+            version: version.to_owned(),
+            description: String::new(),
+            features: Vec::new(),
+            bundle_path: bundle_path.into(),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert_eq!(compare_versions("1.10.0", "1.9.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2", "1.2.0"), Ordering::Less);
+        assert_eq!(compare_versions("2.0.0", "2.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0.0", "1.0.0-beta"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.1", "1.0.beta"), Ordering::Greater);
+        assert_eq!(compare_versions("", "0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn highest_version_wins() {
+        let catalog = PluginCatalog::deduplicated([
+            plugin("com.vendor.a", "1.9.0", "/opt/clap/old/a.clap"),
+            plugin("com.vendor.b", "1.0.0", "/opt/clap/b.clap"),
+            plugin("com.vendor.a", "1.10.0", "/opt/clap/new/a.clap"),
+        ]);
+
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(
+            catalog
+                .get("com.vendor.a")
+                .map(|plugin| plugin.version.as_str()),
+            Some("1.10.0")
+        );
+        assert_eq!(
+            catalog.shadowed(),
+            [ShadowedPlugin {
+                plugin: plugin("com.vendor.a", "1.9.0", "/opt/clap/old/a.clap"),
+                shadowed_by: "/opt/clap/new/a.clap".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn ties_keep_the_copy_found_first() {
+        let catalog = PluginCatalog::deduplicated([
+            plugin("com.vendor.a", "1.0.0", "/opt/clap/a.clap"),
+            plugin("com.vendor.a", "1.0.0", "/usr/lib/clap/a.clap"),
+        ]);
+
+        assert_eq!(
+            catalog
+                .iter()
+                .map(|plugin| &plugin.bundle_path)
+                .collect::<Vec<_>>(),
+            [Path::new("/opt/clap/a.clap")]
+        );
+        assert_eq!(catalog.shadowed().len(), 1);
+        assert_eq!(
+            catalog.shadowed()[0].plugin.bundle_path,
+            Path::new("/usr/lib/clap/a.clap")
+        );
+    }
+
+    #[test]
+    fn user_copies_win() {
+        let Ok(home) = etcetera::home_dir() else {
+            return;
+        };
+        let user_path = home.join(".clap").join("a.clap");
+
+        let catalog = PluginCatalog::deduplicated([
+            plugin("com.vendor.a", "2.0.0", "/usr/lib/clap/a.clap"),
+            plugin("com.vendor.a", "1.0.0", &user_path.to_string_lossy()),
+        ]);
+
+        assert_eq!(
+            catalog
+                .get("com.vendor.a")
+                .map(|plugin| &plugin.bundle_path),
+            Some(&user_path)
+        );
+        assert_eq!(catalog.shadowed()[0].shadowed_by, user_path);
+    }
+
+    #[test]
+    fn distinct_plugins_keep_their_order() {
+        let catalog = PluginCatalog::deduplicated([
+            plugin("com.vendor.b", "1.0.0", "/opt/clap/b.clap"),
+            plugin("com.vendor.a", "1.0.0", "/opt/clap/a.clap"),
+        ]);
+
+        assert_eq!(
+            catalog
+                .iter()
+                .map(|plugin| plugin.id.as_str())
+                .collect::<Vec<_>>(),
+            ["com.vendor.b", "com.vendor.a"]
+        );
+        assert!(catalog.shadowed().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    fmt::{Display, Formatter, Result},
+    str::FromStr,
+};
+
+macro_rules! plugin_features {
+    ($($(#[$meta:meta])* $variant:ident => $name:literal,)*) => {
+        /// A plugin feature, as listed in a plugin's descriptor.
+        ///
+        /// The standard features from the CLAP specification have their own variant, anything else, including vendor
+        /// specific features, is kept as [`PluginFeature::Other`].
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(from = "String", into = "String")]
+        pub enum PluginFeature {
+            $($(#[$meta])* $variant,)*
+            Other(String),
+        }
+
+        impl PluginFeature {
+            #[must_use]
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $name,)*
+                    Self::Other(feature) => feature,
+                }
+            }
+        }
+
+        impl From<&str> for PluginFeature {
+            fn from(feature: &str) -> Self {
+                match feature {
+                    $($name => Self::$variant,)*
+                    feature => Self::Other(feature.to_owned()),
+                }
+            }
+        }
+    };
+}
+
+plugin_features! {
+    // main categories
+    /// the plugin can process note events and then produce audio
+    Instrument => "instrument",
+    /// the plugin is an audio effect
+    AudioEffect => "audio-effect",
+    /// the plugin is a note effect or a note generator/sequencer
+    NoteEffect => "note-effect",
+    /// the plugin converts audio to notes
+    NoteDetector => "note-detector",
+    /// the plugin is an analyzer
+    Analyzer => "analyzer",
+
+    // sub categories
+    Synthesizer => "synthesizer",
+    Sampler => "sampler",
+    Drum => "drum",
+    DrumMachine => "drum-machine",
+    Filter => "filter",
+    Phaser => "phaser",
+    Equalizer => "equalizer",
+    Deesser => "de-esser",
+    PhaseVocoder => "phase-vocoder",
+    Granular => "granular",
+    FrequencyShifter => "frequency-shifter",
+    PitchShifter => "pitch-shifter",
+    Distortion => "distortion",
+    TransientShaper => "transient-shaper",
+    Compressor => "compressor",
+    Expander => "expander",
+    Gate => "gate",
+    Limiter => "limiter",
+    Flanger => "flanger",
+    Chorus => "chorus",
+    Delay => "delay",
+    Reverb => "reverb",
+    Tremolo => "tremolo",
+    Glitch => "glitch",
+    Utility => "utility",
+    PitchCorrection => "pitch-correction",
+    Restoration => "restoration",
+    MultiEffects => "multi-effects",
+    Mixing => "mixing",
+    Mastering => "mastering",
+
+    // audio capabilities
+    Mono => "mono",
+    Stereo => "stereo",
+    Surround => "surround",
+    Ambisonic => "ambisonic",
+}
+
+impl From<String> for PluginFeature {
+    fn from(feature: String) -> Self {
+        match Self::from(feature.as_str()) {
+            Self::Other(_) => Self::Other(feature),
+            known => known,
+        }
+    }
+}
+
+impl From<PluginFeature> for String {
+    fn from(feature: PluginFeature) -> Self {
+        match feature {
+            PluginFeature::Other(feature) => feature,
+            known => known.as_str().to_owned(),
+        }
+    }
+}
+
+impl FromStr for PluginFeature {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl Display for PluginFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PluginInfo;
+
+    #[test]
+    fn standard_features_round_trip() {
+        for feature in [
+            PluginFeature::Instrument,
+            PluginFeature::AudioEffect,
+            PluginFeature::Deesser,
+            PluginFeature::Stereo,
+        ] {
+            assert_eq!(PluginFeature::from(feature.as_str()), feature);
+            assert_eq!(PluginFeature::from(String::from(feature.clone())), feature);
+            assert_eq!(feature.to_string().parse(), Ok(feature));
+        }
+
+        assert_eq!(PluginFeature::from("de-esser"), PluginFeature::Deesser);
+        assert_eq!(PluginFeature::Deesser.as_str(), "de-esser");
+    }
+
+    #[test]
+    fn other_features_are_kept_as_they_are() {
+        let feature = PluginFeature::from("com.vendor.custom");
+
+        assert_eq!(
+            feature,
+            PluginFeature::Other("com.vendor.custom".to_owned())
+        );
+        assert_eq!(feature.as_str(), "com.vendor.custom");
+        assert_eq!(String::from(feature), "com.vendor.custom");
+
+        // features are case sensitive
+        assert_eq!(
+            PluginFeature::from("Instrument"),
+            PluginFeature::Other("Instrument".to_owned())
+        );
+    }
+
+    #[test]
+    fn features_serialize_as_strings() {
+        let features = vec![
+            PluginFeature::Instrument,
+            PluginFeature::Other("com.vendor.custom".to_owned()),
+        ];
+        let json = serde_json::to_string(&features).unwrap();
+
+        assert_eq!(json, r#"["instrument","com.vendor.custom"]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<PluginFeature>>(&json).unwrap(),
+            features
+        );
+    }
+
+    #[test]
+    fn feature_filters() {
+        let plugin = PluginInfo {
+            id: "com.vendor.synth".to_owned(),
+            name: "Synth".to_owned(),
+            vendor: "Vendor".to_owned(),
+            version: "1.0.0".to_owned(),
+            description: String::new(),
+            features: vec![
+                PluginFeature::Instrument,
+                PluginFeature::Synthesizer,
+                PluginFeature::Stereo,
+            ],
+            bundle_path: "/opt/synth.clap".into(),
+            index: 0,
+        };
+
+        assert!(plugin.is_instrument());
+        assert!(!plugin.is_audio_effect());
+        assert!(!plugin.is_note_effect());
+        assert!(!plugin.is_analyzer());
+
+        assert!(plugin.has_features(&[]));
+        assert!(plugin.has_features(&[PluginFeature::Instrument, PluginFeature::Stereo]));
+        assert!(!plugin.has_features(&[PluginFeature::Instrument, PluginFeature::Mono]));
+    }
+}
@@ -0,0 +1,124 @@
+pub use background::ScanHandle;
+pub use cache::ScanCache;
+pub use catalog::{PluginCatalog, PluginInfo, ShadowedPlugin};
+use clack_host::prelude::*;
+use etcetera::{choose_base_strategy, BaseStrategy as _};
+pub use features::PluginFeature;
+pub use plugin_scanner::PluginScanner;
+pub use report::{ScanEntry, ScanEvent, ScanOutcome, ScanReport};
+pub use sandbox::{run_scan_worker, Sandbox};
+use std::{
+    ffi::CStr,
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "watch")]
+pub use watcher::{PluginWatcher, WatchEvent};
+
+mod background;
+mod cache;
+mod catalog;
+mod features;
+mod plugin_scanner;
+mod report;
+mod sandbox;
+#[cfg(feature = "watch")]
+mod watcher;
+
+/// Scans the standard CLAP search paths for plugins.
+///
+/// Every bundle is unloaded again once its descriptors have been read. Results are cached in [`ScanCache`], so only
+/// bundles that changed since the last scan are loaded at all.
+#[must_use]
+pub fn get_installed_plugins() -> PluginCatalog {
+    scan_installed_plugins().catalog()
+}
+
+/// Like [`get_installed_plugins`], but reports the outcome for every candidate path, including the ones that didn't
+/// yield any plugins.
+#[must_use]
+pub fn scan_installed_plugins() -> ScanReport {
+    PluginScanner::new().scan()
+}
+
+fn scan_bundle(path: &Path) -> ScanOutcome {
+    let bundle = match load_bundle(path) {
+        Ok(bundle) => bundle,
+        Err(err) => return ScanOutcome::LoadFailed(err.to_string()),
+    };
+
+    let Some(factory) = bundle.get_plugin_factory() else {
+        return ScanOutcome::MissingFactory;
+    };
+
+    let to_string = |s: Option<&CStr>| {
+        s.map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let plugins: Vec<_> = (0..)
+        .zip(factory.plugin_descriptors())
+        .filter_map(|(index, descriptor)| {
+            Some(PluginInfo {
+                id: descriptor.id()?.to_string_lossy().into_owned(),
+                name: to_string(descriptor.name()),
+                vendor: to_string(descriptor.vendor()),
+                version: to_string(descriptor.version()),
+                description: to_string(descriptor.description()),
+                features: descriptor
+                    .features()
+                    .map(|feature| feature.to_string_lossy().as_ref().into())
+                    .collect(),
+                bundle_path: path.to_owned(),
+                index,
+            })
+        })
+        .collect();
+
+    if plugins.is_empty() {
+        ScanOutcome::NoDescriptors
+    } else {
+        ScanOutcome::Loaded(plugins)
+    }
+}
+
+/// Loads the bundle at `path`, for the crate's own scanning and plugin processes.
+///
+/// Loading runs the bundle's initialization code. Scanning the search roots, or asking a worker process to host a
+/// plugin, means trusting the bundles found there, like any CLAP host does, which is why this isn't exported.
+pub fn load_bundle(path: &Path) -> Result<PluginBundle, PluginBundleError> {
+    // SAFETY: the bundles the crate loads itself are trusted, see above
+    unsafe { PluginBundle::load(path) }
+}
+
+fn standard_clap_paths() -> Vec<PathBuf> {
+    let strategy = choose_base_strategy().unwrap();
+
+    let mut paths = vec![];
+
+    paths.push(strategy.home_dir().join(".clap"));
+
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(val) = std::env::var_os("CommonProgramFiles") {
+            paths.push(PathBuf::from(val).join("CLAP"));
+        }
+
+        paths.push(strategy.config_dir().join("Programs\\Common\\CLAP"));
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        paths.push(strategy.home_dir().join("Library/Audio/Plug-Ins/CLAP"));
+
+        paths.push(PathBuf::from("/Library/Audio/Plug-Ins/CLAP"));
+    }
+
+    #[cfg(target_family = "unix")]
+    paths.push("/usr/lib/clap".into());
+
+    if let Some(env_var) = std::env::var_os("CLAP_PATH") {
+        paths.extend(std::env::split_paths(&env_var));
+    }
+
+    paths
+}
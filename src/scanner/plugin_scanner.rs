@@ -0,0 +1,309 @@
+use super::{
+    cache::BundleStamp, scan_bundle, standard_clap_paths, Sandbox, ScanCache, ScanEntry, ScanEvent,
+    ScanHandle, ScanOutcome, ScanReport,
+};
+use glob::{Pattern, PatternError};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+};
+use walkdir::WalkDir;
+
+/// Configures where and how to look for plugins.
+///
+/// Starts out searching the standard CLAP paths, following symlinks, without any depth limit.
+#[derive(Clone, Debug)]
+pub struct PluginScanner {
+    paths: Vec<PathBuf>,
+    excludes: Vec<Pattern>,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    sandbox: Option<Sandbox>,
+}
+
+impl Default for PluginScanner {
+    fn default() -> Self {
+        Self {
+            paths: standard_clap_paths(),
+            excludes: Vec::new(),
+            max_depth: None,
+            follow_links: true,
+            sandbox: None,
+        }
+    }
+}
+
+impl PluginScanner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The search roots that will be scanned, in order.
+    #[must_use]
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    #[must_use]
+    pub fn add_path(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        if !self.paths.contains(&path) {
+            self.paths.push(path);
+        }
+
+        self
+    }
+
+    #[must_use]
+    pub fn remove_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.paths.retain(|p| p != path.as_ref());
+        self
+    }
+
+    /// Skips every file or directory whose full path matches the glob `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// errors if `pattern` isn't a valid glob
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, PatternError> {
+        self.excludes.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// How many directories deep to descend into each search root, `0` only looks at the roots themselves.
+    #[must_use]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Whether to follow symlinks. Cyclic symlinks are detected and reported, rather than followed forever.
+    #[must_use]
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Loads every bundle in a child process, see [`Sandbox`].
+    #[must_use]
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Scans all search roots, reporting the outcome for every candidate path.
+    ///
+    /// Results are cached in [`ScanCache`], so only bundles that changed since the last scan are loaded at all.
+    #[must_use]
+    pub fn scan(&self) -> ScanReport {
+        self.scan_with(&AtomicBool::new(false), |_| {})
+    }
+
+    /// Scans on a background thread, streaming progress and results through the returned [`ScanHandle`].
+    #[must_use]
+    pub fn scan_in_background(&self) -> ScanHandle {
+        ScanHandle::spawn(self.clone())
+    }
+
+    /// Like [`Self::scan`], but calls `on_event` as the scan progresses, and stops early once `cancel` is set.
+    ///
+    /// All search roots are walked before the first bundle is scanned, so that progress can be reported as
+    /// "`current` out of `total`". Cancelling while the roots are walked returns an empty report.
+    pub fn scan_with(
+        &self,
+        cancel: &AtomicBool,
+        mut on_event: impl FnMut(ScanEvent),
+    ) -> ScanReport {
+        let mut report = ScanReport::default();
+        let mut bundles = Vec::new();
+        let mut seen = HashMap::<PathBuf, PathBuf>::new();
+
+        for dir_entry in self.paths.iter().flat_map(|path| self.walk(path)) {
+            // walking slow or huge directories can take a while by itself
+            if cancel.load(Relaxed) {
+                report.set_cancelled();
+                return report;
+            }
+
+            let dir_entry = match dir_entry {
+                Ok(dir_entry) => dir_entry,
+                Err(err) => {
+                    // a search path that doesn't exist isn't worth reporting
+                    if err.depth() != 0 {
+                        let entry = ScanEntry {
+                            path: err.path().unwrap_or_else(|| Path::new("")).to_owned(),
+                            outcome: ScanOutcome::UnreadableDirectory(err.to_string()),
+                        };
+                        on_event(ScanEvent::Scanned(entry.clone()));
+                        report.push(entry);
+                    }
+                    continue;
+                }
+            };
+
+            if !dir_entry.file_type().is_file()
+                || dir_entry.path().extension().is_none_or(|ext| ext != "clap")
+            {
+                continue;
+            }
+
+            // the same bundle can be reachable through several search paths or symlinks, in which case it's only
+            // loaded once, and the copies found later end up shadowed in the catalog
+            let path = dir_entry.path();
+            let same_as =
+                match seen.entry(fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())) {
+                    Entry::Occupied(entry) => Some(entry.get().clone()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(path.to_owned());
+                        None
+                    }
+                };
+
+            let stamp = dir_entry
+                .metadata()
+                .ok()
+                .and_then(|metadata| BundleStamp::new(&metadata));
+
+            bundles.push((dir_entry.into_path(), stamp, same_as));
+        }
+
+        let mut cache = ScanCache::load();
+        let mut outcomes = HashMap::<&Path, ScanOutcome>::new();
+        let total = bundles.len();
+
+        for (current, (path, stamp, same_as)) in bundles.iter().enumerate() {
+            if cancel.load(Relaxed) {
+                report.set_cancelled();
+                break;
+            }
+
+            on_event(ScanEvent::Progress {
+                path: path.clone(),
+                current: current + 1,
+                total,
+            });
+
+            let outcome = same_as
+                .as_ref()
+                .and_then(|same_as| outcomes.get(same_as.as_path()))
+                .map_or_else(
+                    || self.scan_cached(path, *stamp, &mut cache),
+                    |outcome| outcome.relocated(path),
+                );
+
+            if same_as.is_none() {
+                outcomes.insert(path, outcome.clone());
+            }
+
+            let entry = ScanEntry {
+                path: path.clone(),
+                outcome,
+            };
+            on_event(ScanEvent::Scanned(entry.clone()));
+            report.push(entry);
+        }
+
+        // bundles that weren't visited because of cancellation may well still exist, and the cache is shared with
+        // scanners searching other roots
+        if !report.is_cancelled() {
+            let paths = bundles
+                .iter()
+                .map(|(path, ..)| path.as_path())
+                .collect::<HashSet<_>>();
+            cache.retain(|path| {
+                paths.contains(path) || !self.paths.iter().any(|root| path.starts_with(root))
+            });
+        }
+
+        // a cache that can't be written only costs us a slower scan next time
+        cache.save().ok();
+
+        report
+    }
+
+    /// whether `path` is somewhere this scanner would look for a bundle
+    #[cfg(feature = "watch")]
+    pub(super) fn is_candidate(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "clap")
+            && !path.ancestors().any(|ancestor| {
+                self.excludes
+                    .iter()
+                    .any(|pattern| pattern.matches_path(ancestor))
+            })
+            && self.paths.iter().any(|root| {
+                path.strip_prefix(root).is_ok_and(|relative| {
+                    self.max_depth
+                        .is_none_or(|max_depth| relative.components().count() <= max_depth)
+                })
+            })
+    }
+
+    /// every bundle in all search roots, without loading any of them
+    #[cfg(feature = "watch")]
+    pub(super) fn bundle_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.paths
+            .iter()
+            .flat_map(|root| self.bundle_paths_in(root))
+    }
+
+    #[cfg(feature = "watch")]
+    pub(super) fn bundle_paths_in(&self, root: &Path) -> impl Iterator<Item = PathBuf> + use<> {
+        self.walk(root)
+            .filter_map(Result::ok)
+            .filter(|dir_entry| {
+                dir_entry.file_type().is_file()
+                    && dir_entry
+                        .path()
+                        .extension()
+                        .is_some_and(|ext| ext == "clap")
+            })
+            .map(walkdir::DirEntry::into_path)
+    }
+
+    fn walk(
+        &self,
+        root: &Path,
+    ) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + use<> {
+        let mut walk_dir = WalkDir::new(root).follow_links(self.follow_links);
+
+        if let Some(max_depth) = self.max_depth {
+            walk_dir = walk_dir.max_depth(max_depth);
+        }
+
+        let excludes = self.excludes.clone();
+
+        walk_dir.into_iter().filter_entry(move |dir_entry| {
+            !excludes
+                .iter()
+                .any(|pattern| pattern.matches_path(dir_entry.path()))
+        })
+    }
+
+    /// scans the bundle at `path`, unless `cache` holds the outcome for it already, and caches the outcome
+    pub(super) fn scan_cached(
+        &self,
+        path: &Path,
+        stamp: Option<BundleStamp>,
+        cache: &mut ScanCache,
+    ) -> ScanOutcome {
+        let outcome = stamp
+            .and_then(|stamp| cache.get(path, stamp).cloned())
+            .unwrap_or_else(|| self.scan_bundle(path));
+
+        if let Some(stamp) = stamp {
+            cache.insert(path.to_owned(), stamp, outcome.clone());
+        }
+
+        outcome
+    }
+
+    pub(super) fn scan_bundle(&self, path: &Path) -> ScanOutcome {
+        self.sandbox
+            .as_ref()
+            .map_or_else(|| scan_bundle(path), |sandbox| sandbox.scan(path))
+    }
+}
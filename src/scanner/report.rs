@@ -0,0 +1,193 @@
+use super::PluginCatalog;
+use crate::PluginInfo;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter, Result},
+    path::{Path, PathBuf},
+};
+
+/// What happened to a single candidate path during a scan.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanOutcome {
+    /// the bundle loaded, and exposes these plugins
+    Loaded(Vec<PluginInfo>),
+    /// the bundle couldn't be loaded
+    LoadFailed(String),
+    /// the bundle loaded, but doesn't expose a plugin factory
+    MissingFactory,
+    /// the bundle's plugin factory doesn't describe any plugins
+    NoDescriptors,
+    /// the bundle took down the process scanning it
+    Crashed(String),
+    /// the bundle didn't finish loading in time
+    TimedOut,
+    /// the scanner itself failed, this says nothing about the bundle
+    ScannerFailed(String),
+    /// a directory in the search path couldn't be read
+    UnreadableDirectory(String),
+}
+
+impl ScanOutcome {
+    #[must_use]
+    pub fn plugins(&self) -> &[PluginInfo] {
+        match self {
+            Self::Loaded(plugins) => plugins,
+            _ => &[],
+        }
+    }
+
+    #[must_use]
+    pub fn is_loaded(&self) -> bool {
+        matches!(self, Self::Loaded(..))
+    }
+
+    /// the same outcome for the same bundle found at another `path`, e.g. through a symlink
+    pub(crate) fn relocated(&self, path: &Path) -> Self {
+        match self {
+            Self::Loaded(plugins) => Self::Loaded(
+                plugins
+                    .iter()
+                    .map(|plugin| PluginInfo {
+                        bundle_path: path.to_owned(),
+                        ..plugin.clone()
+                    })
+                    .collect(),
+            ),
+            outcome => outcome.clone(),
+        }
+    }
+
+    /// whether this outcome is a property of the bundle, rather than of the environment it was scanned in
+    ///
+    /// Timeouts aren't, since a bundle may well load in time on a machine that's less busy.
+    pub(crate) fn is_cacheable(&self) -> bool {
+        !matches!(
+            self,
+            Self::TimedOut | Self::ScannerFailed(..) | Self::UnreadableDirectory(..)
+        )
+    }
+}
+
+impl Display for ScanOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Loaded(plugins) => write!(f, "loaded {} plugin(s)", plugins.len()),
+            Self::LoadFailed(err) => write!(f, "failed to load: {err}"),
+            Self::MissingFactory => write!(f, "the bundle doesn't expose a plugin factory"),
+            Self::NoDescriptors => write!(f, "the bundle doesn't contain any plugins"),
+            Self::Crashed(status) => write!(f, "the bundle crashed its scanner process ({status})"),
+            Self::TimedOut => write!(f, "the bundle took too long to load"),
+            Self::ScannerFailed(err) => write!(f, "the scanner failed: {err}"),
+            Self::UnreadableDirectory(err) => write!(f, "couldn't read directory: {err}"),
+        }
+    }
+}
+
+/// Sent while a scan is running, see [`PluginScanner::scan_with`](super::PluginScanner::scan_with).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanEvent {
+    /// bundle number `current` out of `total` is about to be scanned
+    Progress {
+        path: PathBuf,
+        current: usize,
+        total: usize,
+    },
+    /// a candidate path has been scanned
+    Scanned(ScanEntry),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanEntry {
+    pub path: PathBuf,
+    pub outcome: ScanOutcome,
+}
+
+/// Every candidate path visited by a scan, together with its outcome.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    entries: Vec<ScanEntry>,
+    cancelled: bool,
+}
+
+impl ScanReport {
+    #[must_use]
+    pub fn entries(&self) -> &[ScanEntry] {
+        &self.entries
+    }
+
+    /// Whether the scan was cancelled before all candidate paths were scanned.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// All entries that didn't result in any plugins.
+    pub fn failures(&self) -> impl Iterator<Item = &ScanEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.outcome.is_loaded())
+    }
+
+    /// All plugins that were found, see [`PluginCatalog::deduplicated`].
+    #[must_use]
+    pub fn catalog(&self) -> PluginCatalog {
+        PluginCatalog::deduplicated(
+            self.entries
+                .iter()
+                .flat_map(|entry| entry.outcome.plugins())
+                .cloned(),
+        )
+    }
+
+    pub(crate) fn push(&mut self, entry: ScanEntry) {
+        self.entries.push(entry);
+    }
+
+    pub(crate) fn set_cancelled(&mut self) {
+        self.cancelled = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_properties_of_the_bundle_are_cacheable() {
+        assert!(ScanOutcome::Loaded(Vec::new()).is_cacheable());
+        assert!(ScanOutcome::LoadFailed("missing symbol".to_owned()).is_cacheable());
+        assert!(ScanOutcome::MissingFactory.is_cacheable());
+        assert!(ScanOutcome::NoDescriptors.is_cacheable());
+        assert!(ScanOutcome::Crashed("signal: 11".to_owned()).is_cacheable());
+
+        assert!(!ScanOutcome::TimedOut.is_cacheable());
+        assert!(!ScanOutcome::ScannerFailed("couldn't spawn".to_owned()).is_cacheable());
+        assert!(!ScanOutcome::UnreadableDirectory("permission denied".to_owned()).is_cacheable());
+    }
+
+    #[test]
+    fn relocated_outcomes_point_at_the_new_path() {
+        let plugin = PluginInfo {
+            id: "com.vendor.a".to_owned(),
+            name: "A".to_owned(),
+            vendor: String::new(),
+            version: "1.0.0".to_owned(),
+            description: String::new(),
+            features: Vec::new(),
+            bundle_path: "/usr/lib/clap/a.clap".into(),
+            index: 0,
+        };
+
+        let relocated = ScanOutcome::Loaded(vec![plugin]).relocated(Path::new("/opt/clap/a.clap"));
+
+        assert_eq!(relocated.plugins().len(), 1);
+        assert_eq!(
+            relocated.plugins()[0].bundle_path,
+            Path::new("/opt/clap/a.clap")
+        );
+        assert_eq!(
+            ScanOutcome::MissingFactory.relocated(Path::new("/opt/clap/a.clap")),
+            ScanOutcome::MissingFactory
+        );
+    }
+}
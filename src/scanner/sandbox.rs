@@ -0,0 +1,143 @@
+use super::{scan_bundle, ScanOutcome};
+use std::{
+    env,
+    io::{self, Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// set on scan worker processes, holds the path of the bundle to scan
+const WORKER_ENV: &str = "GENERIC_CLAP_HOST_SCAN_BUNDLE";
+/// plugins are free to print to stdout themselves, so the result is marked to be told apart from their output
+const RESULT_MARKER: &str = "generic-clap-host-scan-result:";
+
+/// Scans bundles in a child process each, so that a plugin crashing in its entry point can't take the host down.
+///
+/// The child process is `program` (by default the current executable), which has to call [`run_scan_worker`] at the
+/// start of its `main` function. With the `out-of-process` feature, sandboxes also host plugins through
+/// `run_out_of_process`, in which case `program` has to call `run_plugin_worker` too.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    program: PathBuf,
+    timeout: Duration,
+}
+
+impl Sandbox {
+    #[must_use]
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// errors if the path of the current executable can't be determined
+    pub fn current_exe() -> io::Result<Self> {
+        env::current_exe().map(Self::new)
+    }
+
+    /// How long a single bundle may take to scan, or a hosted plugin may take to answer, before its worker is killed.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[cfg(feature = "out-of-process")]
+    pub(crate) fn program(&self) -> &Path {
+        &self.program
+    }
+
+    #[cfg(feature = "out-of-process")]
+    pub(crate) const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn scan(&self, path: &Path) -> ScanOutcome {
+        let mut child = match Command::new(&self.program)
+            .env(WORKER_ENV, path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                return ScanOutcome::ScannerFailed(format!("couldn't spawn scanner process: {err}"))
+            }
+        };
+
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let deadline = Instant::now() + self.timeout;
+
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Ok(None) => {
+                    child.kill().ok();
+                    child.wait().ok();
+                    return ScanOutcome::TimedOut;
+                }
+                Err(err) => {
+                    return ScanOutcome::ScannerFailed(format!(
+                        "couldn't wait for scanner process: {err}"
+                    ))
+                }
+            }
+        };
+
+        if !status.success() {
+            return ScanOutcome::Crashed(status.to_string());
+        }
+
+        let output = match reader.join().unwrap() {
+            Ok(output) => output,
+            Err(err) => {
+                return ScanOutcome::ScannerFailed(format!("couldn't read scanner output: {err}"))
+            }
+        };
+
+        let Some(json) = output
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix(RESULT_MARKER))
+        else {
+            return ScanOutcome::ScannerFailed("scanner process didn't report a result".to_owned());
+        };
+
+        serde_json::from_str(json).unwrap_or_else(|err| {
+            ScanOutcome::ScannerFailed(format!("couldn't parse scanner output: {err}"))
+        })
+    }
+}
+
+/// Turns the current process into a scan worker if it was spawned by a [`Sandbox`].
+///
+/// Call this at the very start of `main`. In a scan worker it scans the requested bundle, reports the result to the
+/// parent process and exits; otherwise it returns immediately.
+///
+/// # Panics
+///
+/// This will never panic, since scan results always serialize successfully.
+pub fn run_scan_worker() {
+    let Some(path) = env::var_os(WORKER_ENV) else {
+        return;
+    };
+
+    let outcome = scan_bundle(Path::new(&path));
+    let json = serde_json::to_string(&outcome).unwrap();
+
+    let mut stdout = io::stdout().lock();
+    let written = writeln!(stdout, "\n{RESULT_MARKER}{json}").and_then(|()| stdout.flush());
+
+    process::exit(i32::from(written.is_err()));
+}
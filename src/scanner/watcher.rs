@@ -0,0 +1,263 @@
+use super::{cache::BundleStamp, PluginScanner, ScanCache, ScanEntry, ScanOutcome};
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    mem::take,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+/// how long a bundle has to stay untouched before it's scanned, so that half-copied bundles aren't loaded
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// A change to the set of installed bundles, see [`PluginScanner::watch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// a new bundle appeared, and has been scanned
+    Added(ScanEntry),
+    /// a bundle disappeared
+    Removed(PathBuf),
+    /// an existing bundle was modified, and rescanning it had a different outcome
+    Changed(ScanEntry),
+}
+
+/// Watches the search roots of a [`PluginScanner`] for bundles being added, removed or changed.
+///
+/// Dropping the watcher stops watching.
+#[derive(Debug)]
+pub struct PluginWatcher {
+    /// the watching thread only holds a weak reference, so that dropping this stops it
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+}
+
+/// Which search roots are watched, and which ancestors are watched in place of the roots that don't exist yet.
+#[derive(Default)]
+struct Roots {
+    watched: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+    ancestors: HashSet<PathBuf>,
+}
+
+impl Roots {
+    /// Starts watching the roots that were created since the last call, and returns them.
+    ///
+    /// The roots that don't exist are watched for by watching their nearest existing ancestor, and the ones that were
+    /// removed since the last call are watched for again. Roots that fail to be watched are tried again on the next
+    /// call.
+    fn update(&mut self, watcher: &mut impl Watcher) -> (Vec<PathBuf>, notify::Result<()>) {
+        let (removed, existing): (Vec<_>, Vec<_>) = take(&mut self.watched)
+            .into_iter()
+            .partition(|root| !root.is_dir());
+        self.watched = existing;
+
+        for root in &removed {
+            // the watch is usually gone along with the directory already
+            watcher.unwatch(root).ok();
+        }
+        self.missing.extend(removed);
+        self.ancestors.retain(|ancestor| ancestor.is_dir());
+
+        let mut created = Vec::new();
+        let mut result = Ok(());
+
+        for root in take(&mut self.missing) {
+            let found = if root.is_dir() {
+                watcher
+                    .watch(&root, RecursiveMode::Recursive)
+                    .map(|()| true)
+            } else {
+                self.watch_ancestor(&root, watcher).map(|()| false)
+            };
+
+            match found {
+                Ok(true) => {
+                    self.watched.push(root.clone());
+                    created.push(root);
+                }
+                Ok(false) => self.missing.push(root),
+                Err(err) => {
+                    self.missing.push(root);
+                    result = result.and(Err(err));
+                }
+            }
+        }
+
+        (created, result)
+    }
+
+    fn watch_ancestor(&mut self, root: &Path, watcher: &mut impl Watcher) -> notify::Result<()> {
+        match root.ancestors().skip(1).find(|ancestor| ancestor.is_dir()) {
+            Some(ancestor) if !self.ancestors.contains(ancestor) => {
+                watcher.watch(ancestor, RecursiveMode::NonRecursive)?;
+                self.ancestors.insert(ancestor.to_owned());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PluginScanner {
+    /// Starts watching this scanner's search roots, calling `on_event` on a background thread for every bundle that
+    /// is added, removed or changed.
+    ///
+    /// Search roots that don't exist yet are watched as soon as they're created. The outcomes of the bundles scanned
+    /// while watching are cached in [`ScanCache`].
+    ///
+    /// # Errors
+    ///
+    /// errors if the platform's file watching mechanism (inotify on Linux) fails to watch a search root, or the
+    /// nearest existing ancestor of one that doesn't exist yet
+    pub fn watch(
+        &self,
+        mut on_event: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> notify::Result<PluginWatcher> {
+        let (sender, receiver) = channel();
+
+        let mut watcher = recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                if !matches!(event.kind, EventKind::Access(..)) {
+                    sender.send(event.paths).ok();
+                }
+            }
+        })?;
+
+        let mut roots = Roots {
+            missing: self.paths().to_vec(),
+            ..Roots::default()
+        };
+        roots.update(&mut watcher).1?;
+
+        let watcher = Arc::new(Mutex::new(watcher));
+
+        let scanner = self.clone();
+        let weak_watcher = Arc::downgrade(&watcher);
+        thread::spawn(move || scanner.watch_loop(&receiver, &weak_watcher, roots, &mut on_event));
+
+        Ok(PluginWatcher { _watcher: watcher })
+    }
+
+    fn watch_loop(
+        &self,
+        receiver: &Receiver<Vec<PathBuf>>,
+        watcher: &Weak<Mutex<RecommendedWatcher>>,
+        mut roots: Roots,
+        on_event: &mut impl FnMut(WatchEvent),
+    ) {
+        // the bundles that were there all along are only scanned once they change, so their outcome is known if it's
+        // cached
+        let cache = ScanCache::load();
+        let mut known = self
+            .bundle_paths()
+            .map(|path| {
+                let outcome = stamp(&path).and_then(|stamp| cache.get(&path, stamp).cloned());
+                (path, outcome)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut pending = HashSet::new();
+
+        loop {
+            let changed = if pending.is_empty() {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                receiver.recv_timeout(SETTLE_TIME)
+            };
+
+            match changed {
+                Ok(paths) => {
+                    // the ancestors of missing roots are watched as well, but changes outside the roots don't matter
+                    pending.extend(
+                        paths
+                            .into_iter()
+                            .filter(|path| self.paths().iter().any(|root| path.starts_with(root))),
+                    );
+
+                    let Some(watcher) = watcher.upgrade() else {
+                        return;
+                    };
+                    let mut watcher = watcher.lock().unwrap_or_else(PoisonError::into_inner);
+
+                    // bundles may have been moved into a root before it was watched
+                    pending.extend(roots.update(&mut *watcher).0);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let mut cache = ScanCache::load();
+
+                    for path in take(&mut pending) {
+                        self.settle(path, &mut known, &mut cache, on_event);
+                    }
+
+                    cache.save().ok();
+                }
+                // the watcher was dropped
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn settle(
+        &self,
+        path: PathBuf,
+        known: &mut HashMap<PathBuf, Option<ScanOutcome>>,
+        cache: &mut ScanCache,
+        on_event: &mut impl FnMut(WatchEvent),
+    ) {
+        if path.is_dir() {
+            // a whole directory of bundles might have been moved in at once
+            for bundle in self
+                .bundle_paths_in(&path)
+                .filter(|bundle| self.is_candidate(bundle))
+            {
+                self.rescan(bundle, known, cache, on_event);
+            }
+        } else if path.is_file() {
+            if self.is_candidate(&path) {
+                self.rescan(path, known, cache, on_event);
+            }
+        } else {
+            // the path is gone, and with it every bundle inside it
+            let removed = known
+                .keys()
+                .filter(|bundle| bundle.starts_with(&path))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for bundle in removed {
+                known.remove(&bundle);
+                cache.remove(&bundle);
+                on_event(WatchEvent::Removed(bundle));
+            }
+        }
+    }
+
+    fn rescan(
+        &self,
+        path: PathBuf,
+        known: &mut HashMap<PathBuf, Option<ScanOutcome>>,
+        cache: &mut ScanCache,
+        on_event: &mut impl FnMut(WatchEvent),
+    ) {
+        let outcome = self.scan_cached(&path, stamp(&path), cache);
+
+        // a bundle can be touched without changing, and copying one in sends several events for it
+        match known.insert(path.clone(), Some(outcome.clone())) {
+            None => on_event(WatchEvent::Added(ScanEntry { path, outcome })),
+            Some(known) if known.as_ref() != Some(&outcome) => {
+                on_event(WatchEvent::Changed(ScanEntry { path, outcome }));
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Option<BundleStamp> {
+    fs::metadata(path)
+        .ok()
+        .and_then(|metadata| BundleStamp::new(&metadata))
+}
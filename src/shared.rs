@@ -1,4 +1,4 @@
-use crate::MainThreadMessage;
+use crate::{main_thread::MainThreadSender, MainThreadMessage, PluginNotification};
 #[cfg(feature = "gui")]
 use clack_extensions::gui::{GuiSize, HostGuiImpl};
 #[cfg(feature = "params")]
@@ -6,29 +6,36 @@ use clack_extensions::params::HostParamsImplShared;
 #[cfg(feature = "state")]
 use clack_extensions::state::PluginState;
 use clack_host::prelude::*;
-use std::sync::mpsc::Sender;
 #[cfg(feature = "state")]
 use std::sync::OnceLock;
+use std::sync::{
+    atomic::{AtomicBool, Ordering::SeqCst},
+    mpsc::Sender,
+};
 
 pub struct Shared {
-    sender: Sender<MainThreadMessage>,
+    sender: MainThreadSender,
+    notifier: Sender<PluginNotification>,
+    restart_requested: AtomicBool,
     #[cfg(feature = "state")]
     pub state: OnceLock<Option<PluginState>>,
 }
 
 impl SharedHandler<'_> for Shared {
     fn request_process(&self) {
-        // we never pause
+        // the main thread is gone once the plugin is being destroyed, when there's nothing left to do
+        self.sender.send(MainThreadMessage::RequestProcess).ok();
     }
 
     fn request_callback(&self) {
-        self.sender
-            .send(MainThreadMessage::RunOnMainThread)
-            .unwrap();
+        self.sender.send(MainThreadMessage::RunOnMainThread).ok();
     }
 
     fn request_restart(&self) {
-        // we don't support restarting plugins (yet)
+        // repeated requests before the main thread gets around to restarting the plugin only restart it once
+        if !self.restart_requested.swap(true, SeqCst) {
+            self.sender.send(MainThreadMessage::Restart).ok();
+        }
     }
 
     #[cfg(feature = "state")]
@@ -61,23 +68,36 @@ impl HostGuiImpl for Shared {
     }
 
     fn closed(&self, _was_destroyed: bool) {
-        self.sender.send(MainThreadMessage::GuiClosed).unwrap();
+        self.sender.send(MainThreadMessage::GuiClosed).ok();
     }
 }
 
 #[cfg(feature = "params")]
 impl HostParamsImplShared for Shared {
     fn request_flush(&self) {
-        // Can never flush events when not processing: we're never not processing
+        // waking the plugin up lets it flush its parameter changes on the next process call
+        self.request_process();
     }
 }
 
 impl Shared {
-    pub fn new(sender: Sender<MainThreadMessage>) -> Self {
+    pub fn new(sender: MainThreadSender, notifier: Sender<PluginNotification>) -> Self {
         Self {
             sender,
+            notifier,
+            restart_requested: AtomicBool::new(false),
             #[cfg(feature = "state")]
             state: OnceLock::new(),
         }
     }
+
+    /// Called right before the plugin is restarted, so that it can request another restart from then on.
+    pub fn restarting(&self) {
+        self.restart_requested.store(false, SeqCst);
+    }
+
+    pub fn notify(&self, notification: PluginNotification) {
+        // the owner isn't required to listen for notifications
+        self.notifier.send(notification).ok();
+    }
 }